@@ -1,10 +1,73 @@
+use std::fmt::Display;
 use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::{Read, Result, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::retry_state_write;
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory the per chain state files are kept in, `block_numbers/` below the
+/// working directory by default
+///
+/// Only takes effect before any state was read or written, returns whether the state
+/// is kept in `dir`.
+pub fn set_state_dir(dir: impl Into<PathBuf>) -> bool {
+    let dir = dir.into();
+    *STATE_DIR.get_or_init(|| dir.clone()) == dir
+}
+
+/// The directory of the per chain state files, see `set_state_dir`
+pub fn state_dir() -> &'static Path {
+    STATE_DIR.get_or_init(|| PathBuf::from("block_numbers"))
+}
+
+/// Path of the state file `file_name` in `state_dir`
+pub fn state_path(file_name: &str) -> String {
+    state_dir().join(file_name).to_string_lossy().into_owned()
+}
+
+/// Layout of the state files in `block_numbers/`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateFormat {
+    /// The bare decimal number
+    Plain,
+    /// `number\thex_hash\tunix_ts`, with `-` when no hash is known
+    Rich,
+}
+
+/// State file layout selected by the `STATE_FORMAT` env var (`plain` or `rich`)
+pub fn state_format() -> StateFormat {
+    static FORMAT: OnceLock<StateFormat> = OnceLock::new();
+    *FORMAT.get_or_init(|| match std::env::var("STATE_FORMAT") {
+        Ok(format) if format.eq_ignore_ascii_case("rich") => StateFormat::Rich,
+        _ => StateFormat::Plain,
+    })
+}
+
+/// Formats a state file line in the configured `StateFormat`
+pub fn format_state_line(number: impl Display, hash: Option<&str>) -> String {
+    match state_format() {
+        StateFormat::Plain => format!("{}", number),
+        StateFormat::Rich => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            format!("{}\t{}\t{}", number, hash.unwrap_or("-"), now.as_secs())
+        }
+    }
+}
+
+/// The number of a state file line, accepting both the plain and the rich format
+pub fn state_line_number(content: &str) -> &str {
+    content.trim().split('\t').next().unwrap_or_default()
+}
 
 pub fn read_block_number(file_name: &str) -> Option<u128> {
     // File path
-    let file_path = format!("block_numbers/{}-block.txt", file_name);
+    let file_path = state_path(&format!("{}-block.txt", file_name));
 
     // Attempt to open the file
     let mut content = String::new();
@@ -15,7 +78,7 @@ pub fn read_block_number(file_name: &str) -> Option<u128> {
                 eprintln!("Error reading file '{}': {}", file_path, e);
                 return None; // Return a default value on error
             }
-            match content.trim().parse::<u128>() {
+            match state_line_number(&content).parse::<u128>() {
                 Ok(num) => Some(num),
                 Err(e) => {
                     eprintln!("Failed to parse number from file '{}': {}", file_path, e);
@@ -33,37 +96,41 @@ pub fn read_block_number(file_name: &str) -> Option<u128> {
     number
 }
 
-pub fn write_block_number(file_name: &str, number: u128) -> Result<()> {
+/// Writes the next block number of a chain, `hash` is the last forwarded block's
+/// and only kept by the rich `StateFormat`
+pub fn write_block_number(file_name: &str, number: u128, hash: Option<&str>) -> Result<()> {
     // File path
-    let file_path = format!("block_numbers/{}-block.txt", file_name);
+    let file_path = state_path(&format!("{}-block.txt", file_name));
 
     // Ensure the directory exists
     if let Some(parent_dir) = Path::new(&file_path).parent() {
         create_dir_all(parent_dir)?;
     }
 
-    // Open the file for writing
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true) // Overwrite existing content
-        .open(&file_path)
-    {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!(
-                "Failed to open file '{}'. Check if the path is correct and writable. Error: {}",
-                file_path, e
-            );
+    retry_state_write(&file_path, || {
+        // Open the file for writing
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true) // Overwrite existing content
+            .open(&file_path)
+        {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!(
+                    "Failed to open file '{}'. Check if the path is correct and writable. Error: {}",
+                    file_path, e
+                );
+                return Err(e);
+            }
+        };
+
+        // Write the block number to the file
+        if let Err(e) = file.write_all(format_state_line(number, hash).as_bytes()) {
+            eprintln!("Failed to write to file '{}'. Error: {}", file_path, e);
             return Err(e);
         }
-    };
-
-    // Write the block number to the file
-    if let Err(e) = file.write_all(format!("{}", number).as_bytes()) {
-        eprintln!("Failed to write to file '{}'. Error: {}", file_path, e);
-        return Err(e);
-    }
 
-    Ok(())
+        Ok(())
+    })
 }
@@ -1,37 +1,338 @@
 use std::{
-    io::Result, time::Duration
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
 };
-use avail_rust_client::{ext::const_hex, H256, Client, clients::main_client::ChainApi};
-use tokio::time::sleep;
+use avail_rust_client::{H256, Client, clients::main_client::ChainApi};
+use futures_util::StreamExt;
+use serde_json::{json, Map, Value};
+use tokio::{sync::mpsc::Sender, time::sleep};
 use ethabi::{encode, Token};
 use ethers::{
-    core::types::{Address, BlockNumber, Filter, Log, H256 as EthersH256},
-    providers::{Provider, Http as HttpProvider, Middleware},
+    core::types::{Address, BlockNumber, Filter, Log, H256 as EthersH256, U256, U64},
+    providers::{Provider, Http as HttpProvider, Middleware, Ws},
     utils::keccak256,
 };
 
 use crate::{
     block_number_op::{read_block_number, write_block_number},
-    merkle_root_op::{read_last_merkle_root_block, write_last_merkle_root_block, read_last_merkle_root_hash, write_last_merkle_root_hash},
-    rpc_call::rpc::rpc_call,
-    util::{get_rpc_call_params, read_rpc_response},
+    config::{ChainConfig, PacingConfig, ProvidersConfig},
+    error::{BlockReaderError, Result},
+    events::{EventSpec, RootSource, VerifyBatchesEvent},
+    metrics::{Metrics, METRICS},
+    pending::PENDING,
+    merkle_root_op::{
+        merkle_history_size, push_merkle_root, read_last_merkle_root_block, read_last_merkle_root_hash,
+        recent_merkle_roots, write_last_merkle_root_block, write_last_merkle_root_hash,
+    },
+    provider_limit::{OperationPermit, ProviderLimiter, ProviderPermit},
+    rpc_call::rpc::{rpc_call, JsonRpcError},
+    sink::{ProofEvent, KEEPALIVE_LABEL},
+    util::{
+        debug_enabled, decode_block_hash, get_rpc_call_params, parse_hex_quantity, read_blob_gas_fields,
+        read_rpc_response, write_state_blocking,
+    },
 };
 
+/// Computes the topic0 of an event from its canonical signature
+pub fn event_topic(event_signature: &str) -> EthersH256 {
+    EthersH256::from_slice(&keccak256(event_signature.as_bytes()))
+}
+
+/// Whether Avail blocks are read from the finalized head instead of the best one,
+/// from `AVAIL_FINALIZED`
+fn avail_finalized() -> bool {
+    static FINALIZED: OnceLock<bool> = OnceLock::new();
+    *FINALIZED.get_or_init(|| {
+        std::env::var("AVAIL_FINALIZED")
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    })
+}
+
+/// The calls `read_avail_block` makes to the Avail node, so tests can stand in for it
+trait AvailNode {
+    /// Hash and number of the finalized head
+    async fn finalized_head(&self) -> Result<(H256, u32)>;
+    /// Hash of block `number`, the best head when `None`
+    async fn block_hash(&self, number: Option<u32>) -> Result<Option<H256>>;
+    /// Number in the header of the block with `hash`
+    async fn header_number(&self, hash: H256) -> Result<Option<u32>>;
+}
+
+impl AvailNode for Client {
+    async fn finalized_head(&self) -> Result<(H256, u32)> {
+        let finalized = self.finalized().block_info().await?;
+        Ok((finalized.hash, finalized.height))
+    }
+
+    async fn block_hash(&self, number: Option<u32>) -> Result<Option<H256>> {
+        Ok(ChainApi::new(self.clone()).block_hash(number).await?)
+    }
+
+    async fn header_number(&self, hash: H256) -> Result<Option<u32>> {
+        let header = ChainApi::new(self.clone()).block_header(Some(hash)).await?;
+        Ok(header.map(|header| header.number))
+    }
+}
+
+/// Repeats of the same forwarded hash allowed before warning, from
+/// `IDENTICAL_FORWARD_LIMIT` (default 1000), and whether further repeats are skipped,
+/// from `SKIP_IDENTICAL_FORWARDS`
+fn identical_forward_guard() -> (u64, bool) {
+    static GUARD: OnceLock<(u64, bool)> = OnceLock::new();
+    *GUARD.get_or_init(|| {
+        let limit = std::env::var("IDENTICAL_FORWARD_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(1000);
+        let skip = std::env::var("SKIP_IDENTICAL_FORWARDS")
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        (limit, skip)
+    })
+}
+
+/// How many expected block times may pass without a new block before warning
+const STALE_BLOCK_TIME_MULTIPLIER: u32 = 3;
+
+/// Outcome of `fetch_block_hash`
+pub struct FetchedBlock {
+    pub hash: H256,
+    pub height: u128,
+    /// False when the hash equals the last one seen, nothing was forwarded then
+    pub forwarded: bool,
+}
+
 pub struct BlockReader {
-    endpoint: String
+    proof_sender: Sender<ProofEvent>,
+    /// Endpoint of the Avail node `fetch_block_hash` reads from
+    avail_rpc_url: String,
+    /// Client connected to `avail_rpc_url`, created on first use and reused after
+    avail_client: tokio::sync::Mutex<Option<Client>>,
+    /// Last block hash seen per chain and when it was first seen
+    last_seen_blocks: Mutex<HashMap<String, (String, Instant)>>,
+    /// Chains whose head hasn't changed for longer than their stale threshold
+    stale_chains: Mutex<BTreeSet<String>>,
+    /// Highest head number reported for each chain by any of its endpoints
+    max_heads: Mutex<HashMap<String, u128>>,
+    providers: ProviderLimiter,
+    /// Last hash forwarded per chain and how many times in a row it was due to be forwarded
+    identical_forwards: Mutex<HashMap<String, (H256, u64)>>,
+    pacing: PacingConfig,
+    /// Contract chains still catching up to their head
+    catching_up: Mutex<BTreeSet<String>>,
+    /// Last merkle root forwarded per contract chain and the L2 block number it came with
+    merkle_roots: Mutex<HashMap<String, (String, Option<U256>)>>,
+    /// Endpoint that last answered per chain with fallback endpoints, tried first next time
+    active_rpc_urls: Mutex<HashMap<String, String>>,
+    /// Hashes of the recent blocks of chains with reorg detection, by block number
+    recent_blocks: Mutex<HashMap<String, BTreeMap<u128, String>>>,
+    /// Chains whose heads currently arrive over a live `newHeads` subscription
+    subscribed_chains: Mutex<BTreeSet<String>>,
 }
 
 impl BlockReader {
-    pub fn new() -> Self {
-        let zmq_socket_url =
-            std::env::var("ZMQ_CHANNEL_URL").unwrap_or_else(|_| "tcp://0.0.0.0:40006".to_string());
+    pub fn new(
+        proof_sender: Sender<ProofEvent>,
+        avail_rpc_url: &str,
+        providers: &ProvidersConfig,
+        max_concurrency: usize,
+        pacing: PacingConfig,
+    ) -> Self {
+        BlockReader {
+            proof_sender,
+            avail_rpc_url: avail_rpc_url.to_string(),
+            avail_client: tokio::sync::Mutex::new(None),
+            last_seen_blocks: Mutex::new(HashMap::new()),
+            stale_chains: Mutex::new(BTreeSet::new()),
+            max_heads: Mutex::new(HashMap::new()),
+            providers: ProviderLimiter::new(providers, max_concurrency),
+            identical_forwards: Mutex::new(HashMap::new()),
+            pacing,
+            catching_up: Mutex::new(BTreeSet::new()),
+            merkle_roots: Mutex::new(HashMap::new()),
+            active_rpc_urls: Mutex::new(HashMap::new()),
+            recent_blocks: Mutex::new(HashMap::new()),
+            subscribed_chains: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Waits until the global `--max-concurrency` cap has room for another chain operation
+    pub async fn acquire_operation(&self) -> OperationPermit {
+        self.providers.acquire_operation().await
+    }
+
+    /// Waits until the provider group of `chain` has room for another request
+    pub async fn acquire_provider(&self, chain: &ChainConfig) -> Option<ProviderPermit> {
+        self.providers.acquire(chain).await
+    }
 
-        return BlockReader {
-            endpoint: zmq_socket_url,
+    /// Logs when a chain reports the same head as last time
+    ///
+    /// Only warns once the head is older than `STALE_BLOCK_TIME_MULTIPLIER` times the
+    /// chain's expected block time, below that (or without one) it's a debug message.
+    fn check_block_progress(&self, chain: &ChainConfig, block_hash: &str) {
+        let mut last_seen_blocks = self.last_seen_blocks.lock().unwrap();
+        match last_seen_blocks.get(&chain.name) {
+            Some((last_hash, first_seen)) if last_hash == block_hash => {
+                let unchanged_for = first_seen.elapsed();
+                let stale_after = chain
+                    .expected_block_time
+                    .map(|secs| Duration::from_secs(secs) * STALE_BLOCK_TIME_MULTIPLIER);
+                match stale_after {
+                    Some(stale_after) if unchanged_for > stale_after => {
+                        eprintln!(
+                            "No new block of {} for {:?}, expected one every {}s",
+                            chain.name,
+                            unchanged_for,
+                            chain.expected_block_time.unwrap_or_default()
+                        );
+                        self.stale_chains.lock().unwrap().insert(chain.name.clone());
+                    }
+                    _ => {
+                        if debug_enabled() {
+                            eprintln!("No new block of {} for {:?}", chain.name, unchanged_for);
+                        }
+                    }
+                }
+            }
+            _ => {
+                last_seen_blocks.insert(chain.name.clone(), (block_hash.to_string(), Instant::now()));
+                self.stale_chains.lock().unwrap().remove(&chain.name);
+            }
         }
     }
 
-    fn abi_encode_proof(chain_id: i32, block_hash: &H256) -> Vec<u8> {
+    /// Per-chain health for `/status`: the last head seen, for how long it's been
+    /// unchanged, the RPC latency EMA and the pacing mode
+    pub fn status(&self) -> Value {
+        let last_seen_blocks = self.last_seen_blocks.lock().unwrap();
+        let catching_up = self.catching_up.lock().unwrap();
+        let identical_forwards = self.identical_forwards.lock().unwrap();
+        let merkle_roots = self.merkle_roots.lock().unwrap();
+        let mut chains: BTreeSet<String> = last_seen_blocks.keys().cloned().collect();
+        chains.extend(METRICS.latency_chains());
+        chains.extend(METRICS.reply_wait_chains());
+        chains.extend(identical_forwards.keys().cloned());
+        chains.extend(merkle_roots.keys().cloned());
+
+        let status: Map<String, Value> = chains
+            .into_iter()
+            .map(|chain| {
+                let last_seen = last_seen_blocks.get(&chain);
+                let entry = json!({
+                    "last_hash": last_seen.map(|(hash, _)| hash.clone()),
+                    "unchanged_for_secs": last_seen.map(|(_, first_seen)| first_seen.elapsed().as_secs()),
+                    "latency_ema_ms": METRICS.latency_ema_ms(&chain),
+                    "reply_wait_ema_ms": METRICS.reply_wait_ema_ms(&chain),
+                    "identical_forwards": identical_forwards.get(&chain).map(|(_, repeats)| *repeats),
+                    "merkle_root": merkle_roots.get(&chain).map(|(root, l2_block_number)| json!({
+                        "root": root,
+                        "l2_block_number": l2_block_number.map(|number| number.to_string()),
+                    })),
+                    "pacing": if catching_up.contains(&chain) { "catch_up" } else { "normal" },
+                });
+                (chain, entry)
+            })
+            .collect();
+        Value::Object(status)
+    }
+
+    /// Counts consecutive forwards of the same hash of a chain and tells whether this
+    /// one should go ahead
+    ///
+    /// Past `IDENTICAL_FORWARD_LIMIT` repeats a warning is logged, with
+    /// `SKIP_IDENTICAL_FORWARDS` the hash is also skipped until it changes.
+    fn allow_identical_forward(&self, chain_name: &str, hash: &H256) -> bool {
+        let mut identical_forwards = self.identical_forwards.lock().unwrap();
+        let (last_hash, repeats) = identical_forwards
+            .entry(chain_name.to_string())
+            .or_insert((*hash, 0));
+        if last_hash != hash {
+            *last_hash = *hash;
+            *repeats = 0;
+        }
+        *repeats += 1;
+
+        let (limit, skip) = identical_forward_guard();
+        if *repeats <= limit {
+            return true;
+        }
+        if *repeats == limit + 1 {
+            eprintln!(
+                "Warning: {} forwarded the same hash {:?} {} times in a row{}",
+                chain_name,
+                hash,
+                limit,
+                if skip { ", skipping it until it changes" } else { "" }
+            );
+        }
+        !skip
+    }
+
+    /// The label to forward the current head of a chain with, `None` to skip it
+    ///
+    /// Without `keepalive_cycles` the head is forwarded every cycle. With it an
+    /// unchanged head is only forwarded every `keepalive_cycles` cycles, as a keepalive.
+    /// Relies on `allow_identical_forward` having counted this cycle.
+    fn forward_label(&self, chain: &ChainConfig) -> Option<&'static str> {
+        let Some(keepalive_cycles) = chain.keepalive_cycles else {
+            return Some("data");
+        };
+        let repeats = self
+            .identical_forwards
+            .lock()
+            .unwrap()
+            .get(&chain.name)
+            .map_or(1, |(_, repeats)| *repeats);
+        if repeats <= 1 {
+            Some("data")
+        } else if (repeats - 1).is_multiple_of(keepalive_cycles) {
+            Some(KEEPALIVE_LABEL)
+        } else {
+            None
+        }
+    }
+
+    /// Records the head reported by `rpc_url` and tells whether it is behind the highest
+    /// head already seen for the chain
+    ///
+    /// Endpoints of one chain may disagree slightly, a lagging one must not move the
+    /// chain backwards and cause re-forwards.
+    fn is_behind_max_head(&self, chain_name: &str, rpc_url: &str, head: u128) -> bool {
+        let mut max_heads = self.max_heads.lock().unwrap();
+        match max_heads.get(chain_name) {
+            Some(&max_head) if head < max_head => {
+                eprintln!(
+                    "Endpoint {} of {} is behind: reported head {}, highest seen {}",
+                    rpc_url, chain_name, head, max_head
+                );
+                true
+            }
+            _ => {
+                max_heads.insert(chain_name.to_string(), head);
+                false
+            }
+        }
+    }
+
+    /// The shorter interval the loop runs on while any contract chain is catching up
+    pub fn catch_up_interval(&self) -> Option<Duration> {
+        if self.catching_up.lock().unwrap().is_empty() {
+            None
+        } else {
+            Some(Duration::from_secs(self.pacing.catch_up_interval_secs.max(1)))
+        }
+    }
+
+    /// Chains currently considered stale, for `/health`
+    pub fn stale_chains(&self) -> Vec<String> {
+        self.stale_chains.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Encodes a block hash as `(uint256 chainId, bytes32 hash)`, the plain proof payload
+    pub fn abi_encode_proof(chain_id: i32, block_hash: &H256) -> Vec<u8> {
         let tokens = vec![
             Token::Uint(chain_id.into()),
             Token::FixedBytes(block_hash.as_bytes().to_vec()),
@@ -39,175 +340,590 @@ impl BlockReader {
         encode(&tokens)
     }
 
-    pub async fn block_hash_from_rpc(
+    /// Encodes several block hashes of one chain as `(uint256 chainId, bytes32[] hashes)`,
+    /// the payload of a batched `datablock.v2` message
+    pub fn abi_encode_proof_batch(chain_id: i32, block_hashes: &[H256]) -> Vec<u8> {
+        let hashes = block_hashes
+            .iter()
+            .map(|hash| Token::FixedBytes(hash.as_bytes().to_vec()))
+            .collect();
+        encode(&[Token::Uint(chain_id.into()), Token::Array(hashes)])
+    }
+
+    /// Encodes a root with the L2 block number it belongs to as
+    /// `(uint256 chainId, bytes32 root, uint256 l2BlockNumber)`
+    fn abi_encode_proof_with_block_number(chain_id: i32, root: &H256, block_number: U256) -> Vec<u8> {
+        let mut block_number_bytes = [0u8; 32];
+        block_number.to_big_endian(&mut block_number_bytes);
+        let tokens = vec![
+            Token::Uint(chain_id.into()),
+            Token::FixedBytes(root.as_bytes().to_vec()),
+            Token::Uint(block_number_bytes.into()),
+        ];
+        encode(&tokens)
+    }
+
+    /// Encodes a decoded `VerifyBatchesTrustedAggregator` as `(uint256 chainId,
+    /// bytes32 stateRoot, bytes32 exitRoot, uint32 rollupID, uint64 numBatch,
+    /// address aggregator)`, the state root in the same place as in the plain encoding
+    fn abi_encode_verify_batches_proof(chain_id: i32, event: &VerifyBatchesEvent) -> Vec<u8> {
+        let tokens = vec![
+            Token::Uint(chain_id.into()),
+            Token::FixedBytes(event.state_root.as_bytes().to_vec()),
+            Token::FixedBytes(event.exit_root.as_bytes().to_vec()),
+            Token::Uint(event.rollup_id.into()),
+            Token::Uint(event.num_batch.into()),
+            Token::Address(event.aggregator.to_fixed_bytes().into()),
+        ];
+        encode(&tokens)
+    }
+
+    /// Richer proof encoding that also carries the EIP-4844 blob gas fields of the block
+    fn abi_encode_proof_with_blob_gas(
+        chain_id: i32,
+        block_hash: &H256,
+        blob_gas_used: u128,
+        excess_blob_gas: u128,
+    ) -> Vec<u8> {
+        let tokens = vec![
+            Token::Uint(chain_id.into()),
+            Token::FixedBytes(block_hash.as_bytes().to_vec()),
+            Token::Uint(blob_gas_used.into()),
+            Token::Uint(excess_blob_gas.into()),
+        ];
+        encode(&tokens)
+    }
+
+    /// Queues a proof for the sink task
+    ///
+    /// Waits while the channel is full so a slow sink slows fetching down instead of
+    /// dropping proofs. Returns `false` only if the sink task has stopped.
+    async fn forward_proof(
         &self,
         chain_name: &str,
         chain_id: i32,
-        rpc_url: &str,
-        method: &str,
-        auth: Option<&str>,
-    ) -> Result<()> {
-        let mut last_block_number: Option<u128> = read_block_number(chain_name);
-    
+        height: u128,
+        hash: &H256,
+        abi_encoded_proof: Vec<u8>,
+        label: &str,
+    ) -> bool {
+        let mut event = ProofEvent {
+            id: 0,
+            chain_name: chain_name.to_string(),
+            chain_id,
+            height,
+            hash: *hash,
+            abi_encoded_proof,
+            label: label.to_string(),
+        };
+        event.id = PENDING.register(&event);
+        match self.proof_sender.send(event).await {
+            Ok(()) => true,
+            Err(e) => {
+                PENDING.finish(e.0.id);
+                eprintln!("Failed to queue {} of {}: sink stopped", e.0.label, chain_name);
+                false
+            }
+        }
+    }
+
+    /// The proof of an RPC chain's block, with its blob gas fields when the chain forwards
+    /// them and the block has them
+    fn block_proof(chain: &ChainConfig, rpc_response: &Value, hash: &H256) -> Vec<u8> {
+        // Blocks without blob gas fields fall back to the plain encoding
+        let blob_gas_fields = if chain.blob_fields {
+            read_blob_gas_fields(rpc_response)
+        } else {
+            None
+        };
+        match blob_gas_fields {
+            Some((blob_gas_used, excess_blob_gas)) => {
+                eprintln!("blobGasUsed: {}, excessBlobGas: {}", blob_gas_used, excess_blob_gas);
+                Self::abi_encode_proof_with_blob_gas(chain.chain_id, hash, blob_gas_used, excess_blob_gas)
+            }
+            None => Self::abi_encode_proof(chain.chain_id, hash),
+        }
+    }
+
+    /// Fetches the block `confirmations` below the head reported in `latest`, which is
+    /// forwarded instead of the head so it is unlikely to be reorged out
+    async fn confirmed_block(
+        &self,
+        chain: &ChainConfig,
+        latest: Value,
+    ) -> Result<(String, Value)> {
+        let head = read_rpc_response(latest.clone(), &chain.name)
+            .and_then(|(_, number)| number)
+            .and_then(|number| parse_hex_quantity(&number))
+            .ok_or_else(|| {
+                BlockReaderError::InvalidData(format!(
+                    "No head number of {} to count confirmations from: {:?}",
+                    chain.name, latest
+                ))
+            })?;
+        let confirmed = head.saturating_sub(u128::from(chain.confirmations));
+        if debug_enabled() {
+            eprintln!(
+                "Reading block {} of {}, {} confirmations below head {}",
+                confirmed, chain.name, chain.confirmations, head
+            );
+        }
+        self.rpc_call_with_failover(chain, get_rpc_call_params(&chain.name, Some(format!("0x{:x}", confirmed))))
+            .await
+    }
+
+    /// Records the new head of `chain` and re-forwards the blocks below it a reorg replaced
+    ///
+    /// A reorg shows as a head whose parent hash differs from the hash seen before at the
+    /// parent's height, so it is only noticed when the block right below the head was
+    /// seen. The replaced blocks are then fetched by number, walking down the parent
+    /// hashes until one matches what was seen or `depth` blocks were checked, and
+    /// forwarded again oldest first.
+    async fn check_reorg(
+        &self,
+        chain: &ChainConfig,
+        depth: u64,
+        head: u128,
+        hash: &str,
+        parent_hash: Option<&str>,
+    ) {
+        let seen_parent = {
+            let mut recent_blocks = self.recent_blocks.lock().unwrap();
+            let blocks = recent_blocks.entry(chain.name.clone()).or_default();
+            // Blocks above the head were orphaned along with it
+            blocks.split_off(&head);
+            blocks.insert(head, hash.to_lowercase());
+            *blocks = blocks.split_off(&head.saturating_sub(u128::from(depth.saturating_sub(1))));
+            head.checked_sub(1).and_then(|parent| blocks.get(&parent).cloned())
+        };
+        let (Some(mut expected), Some(seen)) = (parent_hash.map(str::to_lowercase), seen_parent) else {
+            return;
+        };
+        if expected == seen {
+            return;
+        }
+        Metrics::incr(&METRICS.reorgs_detected);
+        eprintln!(
+            "Reorg on {}: parent of block {} is {}, {} was seen at {}",
+            chain.name, head, expected, seen, head - 1
+        );
+
+        let mut replaced = Vec::new();
+        let mut height = head - 1;
+        while (replaced.len() as u64) < depth.saturating_sub(1) {
+            let seen = self
+                .recent_blocks
+                .lock()
+                .unwrap()
+                .get(&chain.name)
+                .and_then(|blocks| blocks.get(&height).cloned());
+            if seen.is_none_or(|seen| seen == expected) {
+                break;
+            }
+            let params = get_rpc_call_params(&chain.name, Some(format!("0x{:x}", height)));
+            let response = match self.rpc_call_with_failover(chain, params).await {
+                Ok((_, response)) => response,
+                Err(e) => {
+                    eprintln!("Failed to fetch block {} of {} after a reorg: {}", height, chain.name, e);
+                    break;
+                }
+            };
+            let result = response.get("result");
+            let Some(block_hash) = result.and_then(|result| result.get("hash")).and_then(Value::as_str) else {
+                eprintln!("Block {} of {} missing after a reorg: {:?}", height, chain.name, response);
+                break;
+            };
+            let block_hash = block_hash.to_lowercase();
+            let parent = result
+                .and_then(|result| result.get("parentHash"))
+                .and_then(Value::as_str)
+                .map(str::to_lowercase);
+            replaced.push((height, block_hash, response));
+            match (parent, height.checked_sub(1)) {
+                (Some(parent), Some(below)) => {
+                    expected = parent;
+                    height = below;
+                }
+                _ => break,
+            }
+        }
+
+        for (height, block_hash, response) in replaced.into_iter().rev() {
+            let Some(bytes) = decode_block_hash(&block_hash) else {
+                eprintln!("Invalid block hash {} of {} at {}", block_hash, chain.name, height);
+                continue;
+            };
+            let h256_hash = H256::from(bytes);
+            eprintln!("Re-forwarding block {} of {} after a reorg: {}", height, chain.name, block_hash);
+            let proof = Self::block_proof(chain, &response, &h256_hash);
+            if self
+                .forward_proof(&chain.name, chain.chain_id, height, &h256_hash, proof, "data")
+                .await
+            {
+                Metrics::incr(&METRICS.blocks_reforwarded);
+            }
+            if let Some(blocks) = self.recent_blocks.lock().unwrap().get_mut(&chain.name) {
+                blocks.insert(height, block_hash);
+            }
+        }
+    }
+
+    /// Makes the chain's RPC call, failing over through its fallback endpoints
+    ///
+    /// The endpoint that answered last time is tried first, then the rest in configured
+    /// order. Only an endpoint that can't be reached, times out or doesn't answer with
+    /// JSON, e.g. a 5xx, moves the call on to the next one. A JSON-RPC error is a valid
+    /// answer and returned as is. Returns the
+    /// endpoint that answered along with its response.
+    async fn rpc_call_with_failover(
+        &self,
+        chain: &ChainConfig,
+        params: Vec<Value>,
+    ) -> Result<(String, Value)> {
+        let active = self.active_rpc_urls.lock().unwrap().get(&chain.name).cloned();
+        let mut urls: Vec<&str> = chain.rpc_urls().collect();
+        if let Some(position) = active.as_deref().and_then(|active| urls.iter().position(|url| *url == active)) {
+            let url = urls.remove(position);
+            urls.insert(0, url);
+        }
+
+        let mut last_error = None;
+        for url in urls {
+            let started = Instant::now();
+            let result = rpc_call(
+                url,
+                &chain.method,
+                params.clone(),
+                chain.auth.as_deref(),
+                &chain.headers.0,
+                &chain.rpc_retry,
+            )
+            .await;
+            let answered = match &result {
+                Ok(_) => true,
+                Err(e) => e.downcast_ref::<JsonRpcError>().is_some(),
+            };
+            if answered || chain.fallback_rpc_urls.is_empty() {
+                if answered && active.as_deref().is_some_and(|active| active != url) {
+                    eprintln!("{} switched to RPC endpoint {}", chain.name, url);
+                    METRICS.reset_latency(&chain.name);
+                }
+                METRICS.record_latency(&chain.name, started.elapsed());
+                if answered && !chain.fallback_rpc_urls.is_empty() {
+                    self.active_rpc_urls
+                        .lock()
+                        .unwrap()
+                        .insert(chain.name.clone(), url.to_string());
+                }
+                return result
+                    .map(|response| (url.to_string(), response))
+                    .map_err(BlockReaderError::from_rpc);
+            }
+            if let Err(e) = result {
+                eprintln!("RPC endpoint {} of {} failed, trying the next one: {}", url, chain.name, e);
+                last_error = Some(BlockReaderError::from_rpc(e));
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| BlockReaderError::Rpc(format!("No RPC endpoint of {} answered", chain.name))))
+    }
+
+    /// Reads the head of an RPC chain, or the block `confirmations` below it, and forwards it
+    ///
+    /// A failed request is `Rpc`, an error object from the node `JsonRpc` and a response
+    /// without a usable hash or number `InvalidData`.
+    pub async fn block_hash_from_rpc(&self, chain: &ChainConfig) -> Result<()> {
         let last_block_number_hex = "latest".to_string();
-        match rpc_call(
-            rpc_url,
-            method,
-            get_rpc_call_params(chain_name, Some(last_block_number_hex)),
-            auth,
-        )
-        .await
+        let rpc_result = match self
+            .rpc_call_with_failover(chain, get_rpc_call_params(&chain.name, Some(last_block_number_hex)))
+            .await
         {
-            Ok(rpc_response) => {
-                last_block_number = last_block_number.map(|n| n + 1);
-                if let Some((latest_block_hash, latest_block_number)) =
-                    read_rpc_response(rpc_response.clone(), chain_name)
-                {
-                    println!("{}", '-'.to_string().repeat(50));
-                    if !latest_block_hash.is_none() {
-                        if last_block_number.is_none() {
-                            if let Some(clean_hex_str) = latest_block_number {
-                                let clean_hex_str = clean_hex_str.trim_start_matches("0x");
-                                match u128::from_str_radix(clean_hex_str, 16) {
-                                    Ok(value) => last_block_number = Some(value),
-                                    Err(e) => eprintln!("Error converting hex to u128: {}", e),
-                                }
+            Ok((_, latest)) if chain.confirmations > 0 => self.confirmed_block(chain, latest).await,
+            rpc_result => rpc_result,
+        };
+        match rpc_result {
+            Ok((rpc_url, rpc_response)) => self.forward_head(chain, &rpc_url, rpc_response).await,
+            Err(e) => {
+                eprintln!("Failed to fetch block hash of {}: {}", chain.name, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether the heads of `chain_name` currently arrive over its `newHeads` subscription,
+    /// the loop doesn't poll the chain meanwhile
+    pub fn is_subscribed(&self, chain_name: &str) -> bool {
+        self.subscribed_chains.lock().unwrap().contains(chain_name)
+    }
+
+    /// Subscribes to `newHeads` on the chain's `ws_url` and forwards every head as it
+    /// arrives, for as long as the process runs
+    ///
+    /// Whenever the connection or subscription drops the chain goes back to being polled,
+    /// and the subscription is retried after the chain's `rpc_retry` backoff.
+    pub async fn subscribe_new_heads(self: Arc<Self>, chain: ChainConfig) {
+        let Some(ws_url) = chain.ws_url.clone() else {
+            return;
+        };
+        let mut retry = 0;
+        loop {
+            match self.stream_new_heads(&chain, &ws_url, &mut retry).await {
+                Ok(()) => eprintln!("newHeads subscription of {} ended, polling it meanwhile", chain.name),
+                Err(e) => eprintln!("newHeads subscription of {} failed, polling it meanwhile: {}", chain.name, e),
+            }
+            self.subscribed_chains.lock().unwrap().remove(&chain.name);
+            let delay = chain.rpc_retry.backoff(retry);
+            retry = retry.saturating_add(1);
+            sleep(delay).await;
+        }
+    }
+
+    /// Forwards the heads of one `newHeads` subscription until it ends
+    async fn stream_new_heads(&self, chain: &ChainConfig, ws_url: &str, retry: &mut u32) -> std::result::Result<(), String> {
+        let provider = Provider::<Ws>::connect(ws_url)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", ws_url, e))?;
+        let mut heads = provider
+            .subscribe_blocks()
+            .await
+            .map_err(|e| format!("subscribing at {}: {}", ws_url, e))?;
+        eprintln!("Subscribed to newHeads of {} at {}", chain.name, ws_url);
+        self.subscribed_chains.lock().unwrap().insert(chain.name.clone());
+        *retry = 0;
+
+        while let Some(block) = heads.next().await {
+            // Shaped like an `eth_getBlockByNumber` response, so it's handled like a polled head
+            let head = json!({ "result": serde_json::to_value(&block).map_err(|e| e.to_string())? });
+            let confirmed = if chain.confirmations > 0 {
+                self.confirmed_block(chain, head).await
+            } else {
+                Ok((ws_url.to_string(), head))
+            };
+            match confirmed {
+                Ok((rpc_url, response)) => match self.forward_head(chain, &rpc_url, response).await {
+                    Ok(()) => {}
+                    // One unusable head doesn't end the subscription
+                    Err(e @ BlockReaderError::InvalidData(_)) => {
+                        eprintln!("Skipping a head of {}: {}", chain.name, e)
+                    }
+                    Err(e) => return Err(e.to_string()),
+                },
+                Err(e) => eprintln!("Failed to fetch the confirmed block of {}: {}", chain.name, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards the block of an RPC chain read from `rpc_url`, given as the JSON-RPC
+    /// response of the chain's method, unless it is behind, unchanged or not yet due
+    ///
+    /// A response without a usable hash or number is `InvalidData`.
+    async fn forward_head(&self, chain: &ChainConfig, rpc_url: &str, rpc_response: Value) -> Result<()> {
+        let chain_name = chain.name.as_str();
+        let mut last_block_number: Option<u128> = read_block_number(chain_name).map(|n| n + 1);
+        if let Some((latest_block_hash, latest_block_number)) =
+            read_rpc_response(rpc_response.clone(), chain_name)
+        {
+            eprintln!("{}", '-'.to_string().repeat(50));
+            let reported_head = latest_block_number.as_deref().and_then(parse_hex_quantity);
+            if let Some(head) = reported_head {
+                if self.is_behind_max_head(chain_name, rpc_url, head) {
+                    return Ok(());
+                }
+            }
+            if let Some(latest_block_hash) = latest_block_hash {
+                self.check_block_progress(chain, &latest_block_hash);
+                if let (Some(depth), Some(head)) = (chain.reorg_depth, reported_head) {
+                    let parent_hash = rpc_response
+                        .get("result")
+                        .and_then(|result| result.get("parentHash"))
+                        .and_then(Value::as_str);
+                    self.check_reorg(chain, depth, head, &latest_block_hash, parent_hash).await;
+                }
+                if last_block_number.is_none() {
+                    if let Some(number) = latest_block_number {
+                        match parse_hex_quantity(&number) {
+                            Some(value) => last_block_number = Some(value),
+                            None => {
+                                return Err(BlockReaderError::InvalidData(format!(
+                                    "Invalid block number of {}: {:?}",
+                                    chain_name, number
+                                )))
                             }
                         }
-                        println!(
-                            "New block hash of {} at {}: {}",
-                            chain_name,
-                            last_block_number.unwrap_or_default(),
-                            latest_block_hash.clone().unwrap()
-                        );
-    
-                        // Prepare and send data via ZMQ
-                        let h256_hash = H256::from_slice(
-                            &const_hex::decode(latest_block_hash.unwrap().trim_start_matches("0x"))
-                                .expect("Invalid hex string"),
-                        );
+                    }
+                }
+                eprintln!(
+                    "New block hash of {} at {}: {}",
+                    chain_name,
+                    last_block_number.unwrap_or_default(),
+                    latest_block_hash
+                );
 
-                        println!("h256_hash: {:?}", h256_hash);
-    
-                        let abi_encoded_proof = Self::abi_encode_proof(chain_id, &h256_hash);
-                        println!("abi_encoded_proof: {:?}", abi_encoded_proof);
-                        let data: Vec<Vec<u8>> = vec![
-                            b"datablock".to_vec(),
-                            abi_encoded_proof,
-                            b"!!!!!".to_vec(),
-                        ];
-    
-                        // Create a new ZMQ socket for this operation
-                        let context = zmq::Context::new();
-                        let socket = context
-                            .socket(zmq::REQ)
-                            .expect("Failed to create REQ socket");
-                        
-                        socket
-                            .connect(&self.endpoint)
-                            .expect("Failed to connect to endpoint");
-                        let _ = socket.set_rcvtimeo(20000);
-                        
-                        if let Err(e) = socket.send_multipart(&data, 0) {
-                            eprintln!("Failed to send data via ZMQ: {}", e);
-                        } else {
-                            write_block_number(
-                                chain_name,
-                                last_block_number.unwrap_or_default(),
-                            )?;
-                            sleep(Duration::from_millis(2000)).await;
-                            match socket.recv_string(0) {
-                                Ok(reply) => {
-                                    println!("Received reply: {:?}", reply);
-                                }
-                                Err(e) => eprintln!("Failed to receive reply: {}", e),
-                            };
-                        }
-                        
-                        // Close the socket
-                        socket.disconnect(&self.endpoint).expect("Failed to close socket");
-                    } else {
-                        eprintln!(
-                            "Failed to fetch block by number: {:?}, {:?}",
-                            last_block_number, rpc_response
-                        );
+                // Prepare and send data via ZMQ
+                let h256_hash = match decode_block_hash(&latest_block_hash) {
+                    Some(bytes) => H256::from(bytes),
+                    None => {
+                        return Err(BlockReaderError::InvalidData(format!(
+                            "Invalid block hash of {}, neither 32 bytes of hex nor base64: {}",
+                            chain_name, latest_block_hash
+                        )))
                     }
-                } else {
-                    eprintln!("Malformed response of {}: {:?}", chain_name, rpc_response);
+                };
+
+                eprintln!("h256_hash: {:?}", h256_hash);
+
+                let abi_encoded_proof = Self::block_proof(chain, &rpc_response, &h256_hash);
+
+                if !self.allow_identical_forward(chain_name, &h256_hash) {
+                    return Ok(());
+                }
+                let Some(label) = self.forward_label(chain) else {
+                    if debug_enabled() {
+                        eprintln!("Head of {} unchanged, no keepalive due", chain_name);
+                    }
+                    return Ok(());
+                };
+                if self
+                    .forward_proof(
+                        chain_name,
+                        chain.chain_id,
+                        last_block_number.unwrap_or_default(),
+                        &h256_hash,
+                        abi_encoded_proof,
+                        label,
+                    )
+                    .await
+                {
+                    let state_name = chain_name.to_string();
+                    let next_number = last_block_number.unwrap_or_default();
+                    let hash = latest_block_hash.clone();
+                    write_state_blocking(move || write_block_number(&state_name, next_number, Some(&hash))).await?;
                 }
+            } else {
+                return Err(BlockReaderError::InvalidData(format!(
+                    "No block hash of {} in the response for block {:?}: {:?}",
+                    chain_name, last_block_number, rpc_response
+                )));
             }
-            Err(e) => eprintln!("Failed to fetch block hash {:?}", e),
+        } else {
+            return Err(BlockReaderError::InvalidData(format!(
+                "Malformed response of {}: {:?}",
+                chain_name, rpc_response
+            )));
         }
         Ok(())
     }
 
+    /// The cached Avail client, connecting to `avail_rpc_url` if there is none yet
+    async fn avail_client(&self) -> Result<Client> {
+        let mut cached = self.avail_client.lock().await;
+        if let Some(client) = cached.as_ref() {
+            return Ok(client.clone());
+        }
+        let client = Client::new(&self.avail_rpc_url)
+            .await
+            .map_err(|e| BlockReaderError::Avail(format!("Failed to connect to {}: {}", self.avail_rpc_url, e)))?;
+        eprintln!("Connected to Avail at {}", self.avail_rpc_url);
+        Ok(cached.insert(client).clone())
+    }
+
+    /// Resolves the hash of `block_number` (the head when `None`) and reads the number
+    /// in the header of that same hash, so hash and number always match
+    ///
+    /// With `finalized_only` numbers above the finalized head resolve to it. A missing
+    /// block or a header whose number differs from the requested one is `InvalidData`,
+    /// any other error comes from the node connection.
+    async fn read_avail_block(
+        avail: &impl AvailNode,
+        block_number: Option<u32>,
+        finalized_only: bool,
+    ) -> Result<(H256, u32)> {
+        let (hash, resolved_number) = if finalized_only {
+            // Never go past the finalized head, blocks at or below it can't change
+            let (finalized_hash, finalized_height) = avail.finalized_head().await?;
+            match block_number {
+                Some(number) if number <= finalized_height => (avail.block_hash(Some(number)).await?, Some(number)),
+                _ => (Some(finalized_hash), None),
+            }
+        } else {
+            (avail.block_hash(block_number).await?, block_number)
+        };
+        // Without a hash `block_header` would fall back to the latest header, whose number
+        // doesn't belong to the requested block
+        let hash = hash.ok_or_else(|| {
+            BlockReaderError::InvalidData(match block_number {
+                Some(number) => format!("Avail block {} not found, it may be above the head", number),
+                None => "Avail returned no latest block hash".to_string(),
+            })
+        })?;
+        let header_number = avail.header_number(hash).await?.ok_or_else(|| {
+            BlockReaderError::InvalidData(format!("no Avail header for block hash {:?}", hash))
+        })?;
+        if let Some(number) = resolved_number.filter(|number| *number != header_number) {
+            return Err(BlockReaderError::InvalidData(format!(
+                "Avail header of block {} has number {}, refusing to forward a mismatched pair",
+                number, header_number
+            )));
+        }
+        Ok((hash, header_number))
+    }
+
     pub async fn fetch_block_hash(
         &self,
         identifier: String,
         chain_id: i32,
         _block_number: &str,
         last_block_hash: Option<H256>,
-    ) -> std::result::Result<(H256, u128), Box<dyn std::error::Error>> {
-        let avail = Client::new("https://mainnet.avail-rpc.com/").await.unwrap();
-        let chain = ChainApi::new(avail);
+    ) -> Result<FetchedBlock> {
         let block_number = if !_block_number.is_empty() {
-            match _block_number.trim().parse::<u32>() {
-                Ok(num) => Some(num),
-                Err(_) => None,
-            }
+            _block_number.trim().parse::<u32>().ok()
         } else {
             None
         };
-        let latest_hash = chain.block_hash(block_number).await.unwrap();
-        let latest_block = chain.block_header(last_block_hash).await.unwrap();
+        let avail = self.avail_client().await?;
+        let started = Instant::now();
+        let (latest_hash, latest_block_number) =
+            match Self::read_avail_block(&avail, block_number, avail_finalized()).await {
+                Ok(read) => read,
+                Err(e @ BlockReaderError::InvalidData(_)) => return Err(e),
+                Err(e) => {
+                    // The cached client may have lost its node, retry once on a fresh one
+                    eprintln!("Avail request to {} failed, reconnecting: {}", self.avail_rpc_url, e);
+                    self.avail_client.lock().await.take();
+                    let avail = self.avail_client().await?;
+                    Self::read_avail_block(&avail, block_number, avail_finalized()).await?
+                }
+            };
+        METRICS.record_latency(&identifier, started.elapsed());
 
-        if last_block_hash != latest_hash {
-            println!("{}", '-'.to_string().repeat(50));
-            println!(
+        let forwarded = last_block_hash != Some(latest_hash);
+        if forwarded {
+            eprintln!("{}", '-'.to_string().repeat(50));
+            eprintln!(
                 "New block hash of {} at {}: {:?}",
                 identifier,
-                block_number.unwrap_or_default(),
+                latest_block_number,
                 latest_hash
             );
 
     
-            let abi_encoded_proof = Self::abi_encode_proof(chain_id, &latest_hash.unwrap());
-            println!("abi_encoded_proof: {:?}", abi_encoded_proof);
-            let data: Vec<Vec<u8>> = vec![
-                b"datablock".to_vec(),
-                abi_encoded_proof,
-                b"!!!!!".to_vec(),
-            ];
-            
-            // Create a new ZMQ socket for this operation
-            let context = zmq::Context::new();
-            let socket = context
-                .socket(zmq::REQ)
-                .expect("Failed to create REQ socket");
-            
-            socket
-                .connect(&self.endpoint)
-                .expect("Failed to connect to endpoint");
-            let _ = socket.set_rcvtimeo(20000);
-            
-            if let Err(e) = socket.send_multipart(&data, 0) {
-                eprintln!("Failed to send data via ZMQ: {}", e);
-                return Ok((latest_hash.unwrap(), latest_block.unwrap().number.into()));
-            }
-            
-            sleep(Duration::from_millis(2000)).await;
-            match socket.recv_string(0) {
-                Ok(reply) => {
-                    println!("Received reply: {:?}", reply);
-                }
-                Err(e) => eprintln!("Failed to receive reply: {}", e),
-            };
-            
-            // Close the socket
-            socket.disconnect(&self.endpoint).expect("Failed to close socket");
+            self.forward_proof(
+                &identifier,
+                chain_id,
+                latest_block_number.into(),
+                &latest_hash,
+                Self::abi_encode_proof(chain_id, &latest_hash),
+                "data",
+            )
+            .await;
         }
         
-        Ok((latest_hash.unwrap(), latest_block.unwrap().number.into()))
+        Ok(FetchedBlock {
+            hash: latest_hash,
+            height: latest_block_number.into(),
+            forwarded,
+        })
     }
 
     /// Reads the latest L2MerkleRootAdded event and sends it via ZMQ
@@ -219,139 +935,18 @@ impl BlockReader {
     /// * `chain_name` - The chain name for file tracking
     /// 
     /// # Returns
-    /// * `Result<(), Box<dyn std::error::Error>>` - Success or error
+    /// * `Result<(), BlockReaderError>` - Success or error
     pub async fn read_latest_l2_merkle_root_event(
         &self,
         rpc_url: &str,
         contract_address: Address,
         chain_id: i32,
         chain_name: &str,
-    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        // Create ethers provider
-        let provider = Provider::<HttpProvider>::try_from(rpc_url)?;
-
-        // Get the latest block number
-        let latest_block = provider.get_block_number().await?;
-        
-        // Read the last processed block from file
-        let last_processed_block = read_last_merkle_root_block(chain_name);
-        
-        // Determine the starting block for the search
-        let from_block = match last_processed_block {
-            Some(block) => block + 1, // Start from the next block after last processed
-            None => latest_block, // If no previous tracking, start from latest
-        };
-        
-        // Only proceed if there are new blocks to check
-        if from_block > latest_block {
-            println!("No new blocks to check for L2MerkleRootAdded events");
-            return Ok(());
-        }
-        
-        println!("Checking for L2MerkleRootAdded events from block {} to {}", from_block, latest_block);
-        
-        // Create event signature for L2MerkleRootAdded
-        // Assuming the event signature is: L2MerkleRootAdded(bytes32 indexed merkleRoot, uint256 indexed blockNumber)
-        let event_signature = "L2MerkleRootAdded(bytes32,uint256)";
-        let event_topic = EthersH256::from_slice(&keccak256(event_signature.as_bytes()));
-
-        // Create filter for the L2MerkleRootAdded event
-        let filter = Filter::new()
-            .address(contract_address)
-            .topic0(event_topic)
-            .from_block(BlockNumber::Number(from_block))
-            .to_block(BlockNumber::Number(latest_block));
-
-        // Get logs
-        let logs: Vec<Log> = provider.get_logs(&filter).await?;
-
-        if !logs.is_empty() {
-            println!("Found {} L2MerkleRootAdded events", logs.len());
-            
-            // Process all events found
-            for log in &logs {
-                println!("{}", '-'.to_string().repeat(50));
-                println!(
-                    "L2MerkleRootAdded event found at block {}: {:?}",
-                    log.block_number.unwrap_or_default(),
-                    log
-                );
-
-                // Extract merkle root from the first indexed parameter (topic[1])
-                let merkle_root = if log.topics.len() > 1 {
-                    log.topics[1]
-                } else {
-                    eprintln!("No merkle root found in event topics");
-                    continue;
-                };
-
-                println!("Merkle Root: {:?}", merkle_root);
-
-                // Check if this merkle root was already processed
-                let merkle_root_str = format!("{:?}", merkle_root);
-                if let Some(last_hash) = read_last_merkle_root_hash(chain_name) {
-                    if last_hash == merkle_root_str {
-                        println!("Merkle root already processed, skipping");
-                        continue;
-                    }
-                }
-
-                // ABI encode the merkle root with chain ID
-                // Convert ethers H256 to avail H256 for ABI encoding
-                let avail_h256 = H256::from_slice(merkle_root.as_bytes());
-                let abi_encoded_proof = Self::abi_encode_proof(chain_id, &avail_h256);
-                println!("abi_encoded_proof: {:?}", abi_encoded_proof);
-
-                // Prepare data for ZMQ (same format as block_hash_from_rpc)
-                let data: Vec<Vec<u8>> = vec![
-                    b"datablock".to_vec(),
-                    abi_encoded_proof,
-                    b"!!!!!".to_vec(),
-                ];
-
-                // Create a new ZMQ socket for this operation
-                let context = zmq::Context::new();
-                let socket = context
-                    .socket(zmq::REQ)
-                    .expect("Failed to create REQ socket");
-                
-                socket
-                    .connect(&self.endpoint)
-                    .expect("Failed to connect to endpoint");
-                let _ = socket.set_rcvtimeo(20000);
-                
-                if let Err(e) = socket.send_multipart(&data, 0) {
-                    eprintln!("Failed to send L2MerkleRoot data via ZMQ: {}", e);
-                } else {
-                    sleep(Duration::from_millis(2000)).await;
-                    match socket.recv_string(0) {
-                        Ok(reply) => {
-                            println!("Received reply for L2MerkleRoot: {:?}", reply);
-                        }
-                        Err(e) => eprintln!("Failed to receive reply: {}", e),
-                    };
-                }
-                
-                // Close the socket
-                socket.disconnect(&self.endpoint).expect("Failed to close socket");
-                
-                // Update tracking files with the latest processed event
-                if let Some(block_num) = log.block_number {
-                    write_last_merkle_root_block(chain_name, block_num)?;
-                }
-                write_last_merkle_root_hash(chain_name, &merkle_root_str)?;
-                break;
-            }
-        } else {
-            println!("No new L2MerkleRootAdded events found");
-        }
-        
-        // Update the last processed block even if no events were found
-        write_last_merkle_root_block(chain_name, latest_block)?;
-
-        Ok(())
+    ) -> Result<()> {
+        self.read_latest_event(rpc_url, contract_address, chain_id, chain_name, EventSpec::l2_merkle_root_added())
+            .await
     }
-    
+
     /// Reads the latest VerifyBatchesTrustedAggregator event and sends it via ZMQ
     /// 
     /// # Arguments
@@ -359,23 +954,115 @@ impl BlockReader {
     /// * `contract_address` - The contract address to monitor for VerifyBatchesTrustedAggregator events
     /// * `chain_id` - The chain ID for ABI encoding
     /// * `chain_name` - The chain name for file tracking
+    /// * `rollup_id` - Only forward events of this rollup, `None` forwards any rollup
     /// 
     /// # Returns
-    /// * `Result<(), Box<dyn std::error::Error>>` - Success or error
+    /// * `Result<(), BlockReaderError>` - Success or error
     pub async fn read_latest_verify_batches_trusted_aggregator_event(
         &self,
         rpc_url: &str,
         contract_address: Address,
         chain_id: i32,
         chain_name: &str,
-    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        rollup_id: Option<u32>,
+    ) -> Result<()> {
+        self.read_latest_event(
+            rpc_url,
+            contract_address,
+            chain_id,
+            chain_name,
+            EventSpec::verify_batches_trusted_aggregator(rollup_id),
+        )
+        .await
+    }
+
+    /// Reads the latest `event` of one contract and sends its root via ZMQ
+    ///
+    /// The event's `root_source` says where the root is read from, e.g. an indexed
+    /// topic or the log data, so any event carrying a `bytes32` root can be watched
+    /// without a method of its own.
+    ///
+    /// # Arguments
+    /// * `rpc_url` - The RPC URL of the EVM chain
+    /// * `contract_address` - The contract address emitting the event
+    /// * `chain_id` - The chain ID for ABI encoding
+    /// * `chain_name` - The chain name for file tracking
+    /// * `event` - The event to read and how to extract its root
+    ///
+    /// # Returns
+    /// * `Result<(), BlockReaderError>` - Success or error
+    pub async fn read_latest_event(
+        &self,
+        rpc_url: &str,
+        contract_address: Address,
+        chain_id: i32,
+        chain_name: &str,
+        event: EventSpec,
+    ) -> Result<()> {
+        let chain = ChainConfig::contract(chain_name, chain_id, rpc_url, &format!("{:?}", contract_address))
+            .with_event(event);
+        self.read_contract_events(&chain).await
+    }
+
+    /// Checks a log against its transaction receipt, fetched again after `eth_getLogs`
+    ///
+    /// The log is still included when the receipt exists, is in the same block and holds
+    /// the log at the same index. A missing receipt means the transaction was reorged out.
+    async fn is_log_still_included(
+        provider: &Provider<HttpProvider>,
+        log: &Log,
+    ) -> Result<bool> {
+        let Some(transaction_hash) = log.transaction_hash else {
+            return Ok(false);
+        };
+        let Some(receipt) = provider.get_transaction_receipt(transaction_hash).await? else {
+            return Ok(false);
+        };
+
+        Ok(receipt.block_hash == log.block_hash
+            && receipt.logs.iter().any(|receipt_log| {
+                receipt_log.log_index == log.log_index && receipt_log.topics == log.topics
+            }))
+    }
+
+    /// Scans a contract chain for all of its events and forwards the first new root of each
+    ///
+    /// Every event is queried in the same `eth_getLogs` call by listing their topic0
+    /// values. Logs are handled in chain order and each event keeps its own dedup state,
+    /// the chain's first event in the legacy `{chain}-merkle-hash.txt` file and the others
+    /// in `{chain}-{event}-merkle-hash.txt`. The scanned block range is shared.
+    ///
+    /// # Returns
+    /// * `Result<(), BlockReaderError>` - Success or error
+    pub async fn read_contract_events(
+        &self,
+        chain: &ChainConfig,
+    ) -> Result<()> {
+        let chain_name = chain.name.as_str();
+        let parse_address = |address: &str| {
+            address.parse::<Address>().map_err(|e| {
+                BlockReaderError::InvalidData(format!("Invalid contract address {} of {}: {}", address, chain_name, e))
+            })
+        };
+        let contract_address = parse_address(&chain.contract_address)?;
+        let contract_addresses = chain.contract_addresses().map(parse_address).collect::<Result<Vec<_>>>()?;
+        let event_names = chain
+            .events
+            .iter()
+            .map(|event| event.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
         // Create ethers provider
-        let provider = Provider::<HttpProvider>::try_from(rpc_url)?;
+        let provider = Provider::<HttpProvider>::try_from(chain.rpc_url.as_str())
+            .map_err(|e| BlockReaderError::Rpc(format!("Invalid RPC URL {} of {}: {}", chain.rpc_url, chain_name, e)))?;
 
-        // Get the latest block number
-        let latest_block = provider.get_block_number().await?;
+        // Get the latest block number, only blocks `confirmations` deep are scanned so a
+        // root from a block that reorgs out is never forwarded
+        let head = provider.get_block_number().await?;
+        let latest_block = head.saturating_sub(chain.confirmations.into());
         
-        // Read the last processed block from file
+        // Read the last processed block from file, it never passes the confirmed block
         let last_processed_block = read_last_merkle_root_block(chain_name);
         
         // Determine the starting block for the search
@@ -386,128 +1073,329 @@ impl BlockReader {
         
         // Only proceed if there are new blocks to check
         if from_block > latest_block {
-            println!("No new blocks to check for VerifyBatchesTrustedAggregator events");
+            eprintln!("No new blocks to check for {} events", event_names);
+            self.catching_up.lock().unwrap().remove(chain_name);
             return Ok(());
         }
+
+        // Scan at most the block budget of the current pacing mode
+        let catch_up_lag = self.pacing.catch_up_lag_blocks;
+        let lag = (latest_block - from_block).as_u64();
+        let budget = if catch_up_lag.is_some_and(|catch_up_lag| lag > catch_up_lag) {
+            self.pacing.catch_up_block_budget
+        } else {
+            self.pacing.block_budget
+        };
+        let to_block = match budget {
+            Some(budget) => latest_block.min(from_block + budget.max(1) - 1),
+            None => latest_block,
+        };
+
+        // Catching up until the rest of the range is within the lag
+        let remaining_lag = (latest_block - to_block).as_u64();
+        if catch_up_lag.is_some_and(|catch_up_lag| remaining_lag > catch_up_lag) {
+            if self.catching_up.lock().unwrap().insert(chain_name.to_string()) {
+                eprintln!("{} is {} blocks behind, catching up", chain_name, remaining_lag);
+            }
+        } else if self.catching_up.lock().unwrap().remove(chain_name) {
+            eprintln!("{} caught up, back to normal pacing", chain_name);
+        }
         
-        println!("Checking for VerifyBatchesTrustedAggregator events from block {} to {}", from_block, latest_block);
-        
-        // Create event signature for VerifyBatchesTrustedAggregator
-        // Assuming the event signature is: VerifyBatchesTrustedAggregator (index_topic_1 uint32 rollupID, uint64 numBatch, bytes32 stateRoot, bytes32 exitRoot, index_topic_2 address aggregator)
-        let event_signature = "VerifyBatchesTrustedAggregator(uint32,uint64,bytes32,bytes32,address)";
-        let event_topic = EthersH256::from_slice(&keccak256(event_signature.as_bytes()));
-
-        // Create filter for the VerifyBatchesTrustedAggregator event
-        let filter = Filter::new()
-            .address(contract_address)
-            .topic0(event_topic)
-            .from_block(BlockNumber::Number(from_block))
-            .to_block(BlockNumber::Number(latest_block));
-
-        // Get logs
-        let logs: Vec<Log> = provider.get_logs(&filter).await?;
-
-        if !logs.is_empty() {
-            println!("Found {} VerifyBatchesTrustedAggregator events", logs.len());
-            
-            // Process all events found
-            for log in &logs {
-                println!("{}", '-'.to_string().repeat(50));
-                println!(
-                    "VerifyBatchesTrustedAggregator event found at block {}: {:?}",
+        eprintln!("Checking for {} events from block {} to {}", event_names, from_block, to_block);
+
+        // One query for every event of the contract
+        let mut filter = Filter::new()
+            .address(contract_addresses)
+            .topic0(chain.events.iter().map(EventSpec::topic0).collect::<Vec<_>>());
+
+        // A lone event may narrow the query on its indexed topic1 (e.g. the rollupID),
+        // with several events it is checked per log instead
+        if let [event] = chain.events.as_slice() {
+            if let Some(topic1) = event.topic1_filter() {
+                filter = filter.topic1(topic1);
+            }
+        }
+
+        // Get logs window by window, providers reject `eth_getLogs` over too many blocks.
+        // A failed window ends the scan, the windows before it are still processed and
+        // the next cycle resumes after them.
+        let window = U64::from(self.pacing.log_window_blocks.max(1));
+        let mut logs: Vec<Log> = Vec::new();
+        let mut scanned_to = None;
+        let mut window_error = None;
+        let mut window_start = from_block;
+        let started = Instant::now();
+        while window_start <= to_block {
+            let window_end = to_block.min(window_start + window - 1);
+            let window_filter = filter
+                .clone()
+                .from_block(BlockNumber::Number(window_start))
+                .to_block(BlockNumber::Number(window_end));
+            match provider.get_logs(&window_filter).await {
+                Ok(window_logs) => {
+                    logs.extend(window_logs);
+                    scanned_to = Some(window_end);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Failed to get {} logs of blocks {} to {}: {}",
+                        chain_name, window_start, window_end, e
+                    );
+                    window_error = Some(e);
+                    break;
+                }
+            }
+            window_start = window_end + 1;
+        }
+        METRICS.record_latency(chain_name, started.elapsed());
+        let Some(scanned_to) = scanned_to else {
+            return Err(window_error.map_or_else(
+                || BlockReaderError::InvalidData("no blocks to scan".to_string()),
+                Into::into,
+            ));
+        };
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        // First block with a log not processed this cycle
+        let mut deferred_from: Option<U64> = None;
+
+        for (index, event) in chain.events.iter().enumerate() {
+            let topic0 = event.topic0();
+            let event_logs: Vec<&Log> = logs
+                .iter()
+                .filter(|log| log.topics.first() == Some(&topic0))
+                .collect();
+            if event_logs.is_empty() {
+                eprintln!("No new {} events found", event.name);
+                continue;
+            }
+            eprintln!("Found {} {} events", event_logs.len(), event.name);
+
+            // The first new root of each contract is forwarded, the logs after it are left
+            // for the next cycle
+            let mut forwarded_contracts = HashSet::new();
+            for log in event_logs {
+                if forwarded_contracts.contains(&log.address) {
+                    let block = log.block_number.unwrap_or_default();
+                    deferred_from = Some(deferred_from.map_or(block, |deferred| deferred.min(block)));
+                    continue;
+                }
+                // State of the main contract keeps its original chain based file names,
+                // extra contracts have their address in theirs
+                let state_base = if log.address == contract_address {
+                    chain_name.to_string()
+                } else {
+                    format!("{}-{:?}", chain_name, log.address)
+                };
+                let state_key = if index == 0 {
+                    state_base
+                } else {
+                    format!("{}-{}", state_base, event.name)
+                };
+
+                eprintln!("{}", '-'.to_string().repeat(50));
+                eprintln!(
+                    "{} event found at block {}: {:?}",
+                    event.name,
                     log.block_number.unwrap_or_default(),
                     log
                 );
 
-                // Extract state root from the event data
-                // Event signature: VerifyBatchesTrustedAggregator(uint32 rollupID, uint64 numBatch, bytes32 stateRoot, bytes32 exitRoot, address aggregator)
-                // stateRoot is the 3rd parameter (not indexed), so it's in the data field
-                let merkle_root = if log.data.len() >= 64 { // Need at least 64 bytes for stateRoot (32 bytes) + exitRoot (32 bytes)
-                    // Skip rollupID (32 bytes) and numBatch (32 bytes), then take stateRoot (32 bytes)
-                    let state_root_start = 32; // Skip first 64 bytes (rollupID + numBatch)
-                    let state_root_end = state_root_start + 32; // stateRoot is 32 bytes
-                    
-                    if state_root_end <= log.data.len() {
-                        EthersH256::from_slice(&log.data[state_root_start..state_root_end])
-                    } else {
-                        eprintln!("Insufficient data length for state root extraction");
+                let merkle_root = match event.extract_root(log) {
+                    Ok(merkle_root) => merkle_root,
+                    Err(e) => {
+                        eprintln!("Skipping {} event: {}", event.name, e);
                         continue;
                     }
-                } else {
-                    eprintln!("No state root found in event data");
-                    continue;
                 };
 
-                // Skip if state root is all zeros
-                if merkle_root == EthersH256::zero() {
-                    println!("State root is all zeros (0x0000...00), skipping");
-                    continue;
-                }
+                let l2_block_number = match event.extract_block_number(log) {
+                    Ok(l2_block_number) => l2_block_number,
+                    Err(e) => {
+                        eprintln!("Skipping {} event: {}", event.name, e);
+                        continue;
+                    }
+                };
 
-                println!("Merkle Root: {:?}", merkle_root);
+                eprintln!("Merkle Root: {:?}", merkle_root);
+                if let Some(l2_block_number) = l2_block_number {
+                    eprintln!("L2 Block Number: {}", l2_block_number);
+                }
 
-                // Check if this merkle root was already processed
+                // Check if this merkle root was already processed, recently or last
                 let merkle_root_str = format!("{:?}", merkle_root);
-                if let Some(last_hash) = read_last_merkle_root_hash(chain_name) {
-                    if last_hash == merkle_root_str {
-                        println!("Merkle root already processed, skipping");
-                        continue;
-                    }
+                let already_processed = read_last_merkle_root_hash(&state_key).as_ref() == Some(&merkle_root_str)
+                    || recent_merkle_roots(&state_key, merkle_history_size())
+                        .iter()
+                        .any(|record| record.hash == merkle_root_str);
+                if already_processed {
+                    eprintln!("Merkle root already processed, skipping");
+                    continue;
+                }
+
+                if chain.verify_receipt && !Self::is_log_still_included(&provider, log).await? {
+                    eprintln!(
+                        "{} event at block {} is no longer included (reorged out), skipping",
+                        event.name,
+                        log.block_number.unwrap_or_default()
+                    );
+                    continue;
                 }
 
-                // ABI encode the merkle root with chain ID
                 // Convert ethers H256 to avail H256 for ABI encoding
                 let avail_h256 = H256::from_slice(merkle_root.as_bytes());
-                let abi_encoded_proof = Self::abi_encode_proof(chain_id, &avail_h256);
-                println!("abi_encoded_proof: {:?}", abi_encoded_proof);
-
-                // Prepare data for ZMQ (same format as block_hash_from_rpc)
-                let data: Vec<Vec<u8>> = vec![
-                    b"datablock".to_vec(),
-                    abi_encoded_proof,
-                    b"!!!!!".to_vec(),
-                ];
-
-                // Create a new ZMQ socket for this operation
-                let context = zmq::Context::new();
-                let socket = context
-                    .socket(zmq::REQ)
-                    .expect("Failed to create REQ socket");
-                
-                socket
-                    .connect(&self.endpoint)
-                    .expect("Failed to connect to endpoint");
-                let _ = socket.set_rcvtimeo(20000);
-                
-                if let Err(e) = socket.send_multipart(&data, 0) {
-                    eprintln!("Failed to send VerifyBatchesTrustedAggregator data via ZMQ: {}", e);
-                } else {
-                    sleep(Duration::from_millis(2000)).await;
-                    match socket.recv_string(0) {
-                        Ok(reply) => {
-                            println!("Received reply for VerifyBatchesTrustedAggregator: {:?}", reply);
-                        }
-                        Err(e) => eprintln!("Failed to receive reply: {}", e),
-                    };
-                }
-                
-                // Close the socket
-                socket.disconnect(&self.endpoint).expect("Failed to close socket");
+                // VerifyBatches proofs carry the whole decoded event, it decoded fine above
+                let verify_batches = match event.root_source {
+                    RootSource::VerifyBatchesStateRoot { .. } => VerifyBatchesEvent::decode(log).ok(),
+                    _ => None,
+                };
+                let proof = match (verify_batches, l2_block_number) {
+                    (Some(verify_batches), _) => {
+                        Self::abi_encode_verify_batches_proof(chain.chain_id, &verify_batches)
+                    }
+                    (None, Some(l2_block_number)) => Self::abi_encode_proof_with_block_number(
+                        chain.chain_id,
+                        &avail_h256,
+                        l2_block_number,
+                    ),
+                    (None, None) => Self::abi_encode_proof(chain.chain_id, &avail_h256),
+                };
+                self.forward_proof(
+                    chain_name,
+                    chain.chain_id,
+                    log.block_number.unwrap_or_default().as_u64().into(),
+                    &avail_h256,
+                    proof,
+                    &event.label,
+                )
+                .await;
+                self.merkle_roots
+                    .lock()
+                    .unwrap()
+                    .insert(chain_name.to_string(), (merkle_root_str.clone(), l2_block_number));
                 
-                // Update tracking files with the latest processed event
-                if let Some(block_num) = log.block_number {
-                    write_last_merkle_root_block(chain_name, block_num)?;
-                }
-                write_last_merkle_root_hash(chain_name, &merkle_root_str)?;
-                break;
+                let root_block = log.block_number.unwrap_or_default();
+                let root = merkle_root_str.clone();
+                write_state_blocking(move || {
+                    write_last_merkle_root_hash(&state_key, &root)?;
+                    push_merkle_root(&state_key, root_block, &root)
+                })
+                .await?;
+                forwarded_contracts.insert(log.address);
             }
-        } else {
-            println!("No new VerifyBatchesTrustedAggregator events found");
         }
         
-        // Update the last processed block even if no events were found
-        write_last_merkle_root_block(chain_name, latest_block)?;
+        // Advance to the last block fully processed, even if no events were found. Logs
+        // left for the next cycle are scanned again from their block, the roots already
+        // forwarded from it are then skipped as processed.
+        let processed_to = match deferred_from {
+            Some(deferred_from) => scanned_to.min(deferred_from.saturating_sub(U64::one())),
+            None => scanned_to,
+        };
+        match last_processed_block {
+            Some(last) if processed_to <= last => {
+                if processed_to < last {
+                    eprintln!(
+                        "Not moving the {} cursor back from block {} to {}",
+                        chain_name, last, processed_to
+                    );
+                }
+            }
+            _ => {
+                let state_name = chain_name.to_string();
+                write_state_blocking(move || write_last_merkle_root_block(&state_name, processed_to, None)).await?;
+            }
+        }
 
-        Ok(())
+        match window_error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An Avail node with blocks `0..=head`, the hash of block `n` being `n` repeated
+    /// in every byte, that records the hashes whose header was read
+    struct StubAvail {
+        head: u32,
+        finalized: u32,
+        header_reads: Mutex<Vec<H256>>,
+    }
+
+    impl StubAvail {
+        fn new(head: u32, finalized: u32) -> Self {
+            StubAvail {
+                head,
+                finalized,
+                header_reads: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn hash_of(number: u32) -> H256 {
+            H256::repeat_byte(number as u8)
+        }
+    }
+
+    impl AvailNode for StubAvail {
+        async fn finalized_head(&self) -> Result<(H256, u32)> {
+            Ok((Self::hash_of(self.finalized), self.finalized))
+        }
+
+        async fn block_hash(&self, number: Option<u32>) -> Result<Option<H256>> {
+            let number = number.unwrap_or(self.head);
+            Ok((number <= self.head).then(|| Self::hash_of(number)))
+        }
+
+        async fn header_number(&self, hash: H256) -> Result<Option<u32>> {
+            self.header_reads.lock().unwrap().push(hash);
+            Ok((0..=self.head).find(|number| Self::hash_of(*number) == hash))
+        }
+    }
+
+    #[tokio::test]
+    async fn avail_hash_and_number_belong_to_the_same_block() {
+        let avail = StubAvail::new(120, 100);
+        assert_eq!(
+            BlockReader::read_avail_block(&avail, None, false).await.unwrap(),
+            (StubAvail::hash_of(120), 120)
+        );
+        assert_eq!(
+            BlockReader::read_avail_block(&avail, None, true).await.unwrap(),
+            (StubAvail::hash_of(100), 100)
+        );
+        // Above the finalized head the pair is the finalized block, not the request
+        assert_eq!(
+            BlockReader::read_avail_block(&avail, Some(110), true).await.unwrap(),
+            (StubAvail::hash_of(100), 100)
+        );
+        assert!(matches!(
+            BlockReader::read_avail_block(&avail, Some(121), false).await,
+            Err(BlockReaderError::InvalidData(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn historical_avail_block_keeps_its_number() {
+        let avail = StubAvail::new(120, 100);
+        for finalized_only in [false, true] {
+            let (hash, number) = BlockReader::read_avail_block(&avail, Some(42), finalized_only).await.unwrap();
+            assert_eq!(number, 42);
+            assert_eq!(hash, StubAvail::hash_of(42));
+        }
+    }
+
+    #[tokio::test]
+    async fn avail_header_is_read_for_the_forwarded_hash() {
+        let avail = StubAvail::new(120, 100);
+        // As in the loop: the head first, then the block after the last forwarded one
+        BlockReader::read_avail_block(&avail, None, false).await.unwrap();
+        let (hash, number) = BlockReader::read_avail_block(&avail, Some(77), false).await.unwrap();
+        assert_eq!((hash, number), (StubAvail::hash_of(77), 77));
+        // Only ever the header of the hash being returned, never a previous block's
+        let reads = avail.header_reads.lock().unwrap().clone();
+        assert_eq!(reads, vec![StubAvail::hash_of(120), StubAvail::hash_of(77)]);
     }
 }
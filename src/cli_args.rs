@@ -1,6 +1,9 @@
-use clap::{Parser, ValueEnum};
+use std::{path::PathBuf, str::FromStr};
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Clone, ValueEnum)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Mode {
     /// Run in Test Mode where it calls local function for testing
     TEST,
@@ -12,6 +15,46 @@ pub enum Mode {
     BOTH,
 }
 
+/// Where forwarded proofs are delivered
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkKind {
+    /// The aggregator over ZMQ
+    Zmq,
+    /// Newline-delimited JSON on stdout, logs stay on stderr
+    Jsonl,
+    /// A co-located aggregator listening on a Unix domain socket
+    Unix(PathBuf),
+    /// Rotating segment files in a directory, for archival and later replay
+    Segment(PathBuf),
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "zmq" => Ok(SinkKind::Zmq),
+            "jsonl" => Ok(SinkKind::Jsonl),
+            _ => {
+                if let Some(path) = value.strip_prefix("unix:") {
+                    return match path {
+                        "" => Err("missing socket path, expected unix:/path/to/sock".to_string()),
+                        path => Ok(SinkKind::Unix(PathBuf::from(path))),
+                    };
+                }
+                match value.strip_prefix("segment:") {
+                    Some("") => Err("missing directory, expected segment:/path/to/dir".to_string()),
+                    Some(path) => Ok(SinkKind::Segment(PathBuf::from(path))),
+                    None => Err(format!(
+                        "unknown sink '{}', expected zmq, jsonl, unix:/path/to/sock or segment:/path/to/dir",
+                        value
+                    )),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "Layeredge Block Reader")]
 #[command(author, version, about, long_about = None)]
@@ -19,4 +62,107 @@ pub struct Args {
     /// Run Server in Different Modes
     #[arg(long, short, value_enum, default_value_t = Mode::REST)]
     pub mode: Mode,
+    /// Path to a JSON config file, missing sections fall back to their defaults
+    #[arg(long, short)]
+    pub config: Option<String>,
+    /// Destination of forwarded proofs: zmq, jsonl, unix:/path/to/sock or segment:/path/to/dir
+    #[arg(long, env = "SINK", default_value = "zmq")]
+    pub sink: SinkKind,
+    /// File touched after every completed loop cycle so a watchdog can alert on a stale mtime
+    #[arg(long, env = "HEARTBEAT_FILE")]
+    pub heartbeat_file: Option<String>,
+    /// Also touch `<heartbeat-file>.<chain>` after each chain is processed successfully
+    #[arg(long, env = "HEARTBEAT_PER_CHAIN", requires = "heartbeat_file")]
+    pub heartbeat_per_chain: bool,
+    /// Identifies this reader deployment to a shared aggregator, sent as an extra
+    /// frame after the terminator with every proof (omitted when empty)
+    #[arg(long, env = "READER_NAMESPACE", default_value = "")]
+    pub namespace: String,
+    /// Chain ID encoded into Avail proofs, required in LOOP and BOTH mode
+    #[arg(long, env = "AVAIL_CHAIN_ID", value_parser = clap::value_parser!(i32).range(1..))]
+    pub avail_chain_id: Option<i32>,
+    /// Avail node the `avail` chain is read from, e.g. a Turing testnet or private node
+    #[arg(long, env = "AVAIL_RPC_URL", default_value = "https://mainnet.avail-rpc.com/")]
+    pub avail_rpc_url: String,
+    /// Chain ID encoded into the proofs of the add route, required in REST and BOTH mode
+    #[arg(long, env = "O3_CHAIN_ID", value_parser = clap::value_parser!(i32).range(1..))]
+    pub o3_chain_id: Option<i32>,
+    /// Name the add route forwards under, in logs, the sent-log and `/forward-history/`
+    #[arg(long, env = "REST_CHAIN_NAME", default_value = "o3")]
+    pub rest_chain_name: String,
+    /// Skip the contract_allowlist check of the config file, for development only
+    #[arg(long)]
+    pub no_address_allowlist: bool,
+    /// Exit after this many loop cycles, failing if any cycle had errors (default unbounded)
+    #[arg(long)]
+    pub max_cycles: Option<u64>,
+    /// Seconds to wait before the first loop cycle, giving the aggregator and nodes
+    /// started alongside the reader time to come up
+    #[arg(long, env = "STARTUP_DELAY_SECS", default_value_t = 0)]
+    pub startup_delay: u64,
+    /// Seconds a chain's whole processing in a loop cycle may take before it is abandoned
+    /// until the next cycle, unset never gives up on a chain
+    #[arg(long, env = "CHAIN_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub chain_timeout: Option<u64>,
+    /// Maximum chain operations running at once across the loop and the REST add route
+    /// (default the number of CPUs)
+    #[arg(long, env = "MAX_CONCURRENCY", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_concurrency: Option<usize>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the keccak256 topic0 of an event signature, e.g. "L2MerkleRootAdded(bytes32,uint256)"
+    Topic {
+        /// Canonical event signature without parameter names or spaces
+        signature: String,
+    },
+    /// Make one RPC call and show what the block hash/number parser extracts, nothing is forwarded
+    Probe {
+        /// RPC endpoint to call
+        #[arg(long)]
+        url: String,
+        /// RPC method, e.g. eth_getBlockByNumber; repeated methods are sent as one batch
+        #[arg(long = "method", required = true)]
+        methods: Vec<String>,
+        /// Chain kind selecting the params and parser, e.g. celestia, kaanch, tron (default EVM)
+        #[arg(long, default_value = "evm")]
+        chain: String,
+        /// Authorization header value
+        #[arg(long)]
+        auth: Option<String>,
+        /// Extra request header as "Name: value", may be repeated
+        #[arg(long = "header")]
+        headers: Vec<String>,
+        /// JSON pointer to the block hash, overrides the chain parser (e.g. /result/hash)
+        #[arg(long)]
+        hash_pointer: Option<String>,
+        /// JSON pointer to the block number, overrides the chain parser (e.g. /result/number)
+        #[arg(long)]
+        number_pointer: Option<String>,
+    },
+    /// Print the forwards of a running instance live, reconnecting when the connection drops
+    Tail {
+        /// WebSocket endpoint of the running instance
+        #[arg(long, default_value = "ws://localhost:8080/ws")]
+        url: String,
+    },
+    /// Re-send previously forwarded proofs through the sink given by --sink, from the
+    /// segment sink's archive or a sent-log
+    Replay {
+        /// A segment file, or a segment directory to replay all of its segments in order
+        #[arg(required_unless_present = "from_audit", conflicts_with = "from_audit")]
+        segment: Option<PathBuf>,
+        /// A sent-log to replay instead, e.g. block_numbers/linea-sent.log
+        #[arg(long)]
+        from_audit: Option<PathBuf>,
+        /// Only replay proofs of this chain
+        #[arg(long)]
+        chain: Option<String>,
+        /// Only replay proofs forwarded at or after this unix timestamp (seconds)
+        #[arg(long)]
+        since: Option<u64>,
+    },
 }
@@ -0,0 +1,593 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs,
+    io::Result,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::events::EventSpec;
+
+/// Top level config file, every section is optional and falls back to its defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub sink: SinkConfig,
+    pub providers: ProvidersConfig,
+    pub pacing: PacingConfig,
+    /// Extra events scanned on contract chains, by chain name
+    pub events: HashMap<String, Vec<EventConfig>>,
+    /// Throttles `/add-block-by-number/`, each request costs an Avail RPC call and a forward
+    pub add_block_rate_limit: RateLimitConfig,
+    /// Contract addresses contract chains may watch, unset allows any address
+    pub contract_allowlist: Option<Vec<String>>,
+    /// Extra HTTP headers sent with the RPC requests of a chain, by chain name
+    pub headers: HashMap<String, RpcHeaders>,
+    /// Further contract addresses watched on contract chains, by chain name
+    pub contract_addresses: HashMap<String, Vec<String>>,
+    /// Rotation of the `segment:` sink's files
+    pub segments: SegmentConfig,
+    /// Also forward the unchanged head of an RPC chain every this many cycles as a
+    /// keepalive, and otherwise only on a hash change; unset forwards the head every cycle
+    pub keepalive_cycles: Option<u64>,
+    /// Retries of the JSON-RPC calls of RPC chains
+    pub rpc_retry: RetryConfig,
+    /// Remember this many recent block hashes of each EVM RPC chain and re-forward the
+    /// blocks a reorg replaced; unset disables reorg detection
+    pub reorg_depth: Option<u64>,
+}
+
+impl Config {
+    /// Loads the JSON config file at `path`, or the defaults when no path is given
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)?;
+                let config: Config = serde_json::from_str(&content).map_err(|e| {
+                    std::io::Error::other(format!("Invalid config file '{}': {}", path, e))
+                })?;
+                config.validate().map_err(|e| {
+                    std::io::Error::other(format!("Invalid config file '{}': {}", path, e))
+                })?;
+                Ok(config)
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Checks what serde can't, i.e. that header names and values are valid HTTP
+    fn validate(&self) -> std::result::Result<(), String> {
+        for (chain, headers) in &self.headers {
+            for (name, value) in &headers.0 {
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header name '{}' of {}", name, chain))?;
+                HeaderValue::from_str(value)
+                    .map_err(|_| format!("invalid value of header '{}' of {}", name, chain))?;
+            }
+        }
+        for (chain, endpoint) in &self.sink.endpoints {
+            validate_zmq_endpoint(endpoint)
+                .map_err(|e| format!("invalid ZMQ endpoint '{}' of {}: {}", endpoint, chain, e))?;
+        }
+        if self.keepalive_cycles == Some(0) {
+            return Err("keepalive_cycles must be at least 1".to_string());
+        }
+        if self.pacing.log_window_blocks == 0 {
+            return Err("pacing.log_window_blocks must be at least 1".to_string());
+        }
+        if self.reorg_depth == Some(0) {
+            return Err("reorg_depth must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Fails unless every contract chain watches an address on `contract_allowlist`
+    ///
+    /// Guards against a typo forwarding roots from the wrong contract. Addresses are
+    /// compared case-insensitively, so checksummed and lowercase forms both match.
+    pub fn check_contract_allowlist(&self, chains: &[ChainConfig]) -> Result<()> {
+        let Some(allowlist) = &self.contract_allowlist else {
+            return Ok(());
+        };
+        for chain in chains.iter().filter(|chain| chain.kind == ChainKind::Contract) {
+            for address in chain.contract_addresses() {
+                if !allowlist
+                    .iter()
+                    .any(|allowed| allowed.trim().eq_ignore_ascii_case(address.trim()))
+                {
+                    return Err(std::io::Error::other(format!(
+                        "Contract {} of {} is not on the contract_allowlist",
+                        address, chain.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the sink waits for the aggregator to acknowledge a proof
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AckMode {
+    /// Wait for a reply and judge it with the reply criterion
+    Wait,
+    /// Fire-and-forget, a successful send counts as delivered
+    None,
+}
+
+/// How the ZMQ sink uses several aggregator endpoints listed in `ZMQ_CHANNEL_URL`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointMode {
+    /// Send to one endpoint at a time, moving on to the next when a delivery fails
+    #[default]
+    Failover,
+    /// Send every proof to all endpoints
+    Broadcast,
+}
+
+/// Whether the proofs of a chain must reach the sink in the order they were queued
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainOrdering {
+    /// One delivery of the chain at a time, in queue order
+    #[default]
+    Strict,
+    /// Delivered as soon as the in-flight window allows, possibly out of order
+    Relaxed,
+}
+
+/// Delivery tuning for the aggregator sink
+///
+/// Defaults match the historical hardcoded behavior, a 20s receive timeout, no retries
+/// and waiting for an ack, except that the reply is read right after sending and
+/// failed sends are retried.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SinkConfig {
+    /// Capacity of the channel between the fetchers and the sink task,
+    /// fetches wait once it is full
+    pub queue_capacity: usize,
+    /// Maximum number of proofs awaiting an aggregator reply at once, the default
+    /// of 1 delivers strictly one after another like a single REQ/REP socket
+    pub max_in_flight: usize,
+    /// Maximum number of queued proofs of one chain sent as a single batched message,
+    /// the default of 1 sends every proof on its own
+    pub batch_size: usize,
+    /// Receive timeout for the aggregator reply in milliseconds, the reply is read as
+    /// soon as it arrives
+    pub recv_timeout_ms: u64,
+    /// Number of retries after a failed delivery attempt
+    pub retries: u32,
+    /// Number of times a failed send is retried on a reconnected socket within one
+    /// delivery attempt, spaced by the retry backoff
+    pub send_retries: u32,
+    /// Base delay of the exponential retry backoff in milliseconds
+    pub backoff_base_ms: u64,
+    /// Upper bound of the retry backoff in milliseconds
+    pub backoff_max_ms: u64,
+    pub ack_mode: AckMode,
+    /// Delivery ordering by chain name, chains not listed are strict
+    pub ordering: HashMap<String, ChainOrdering>,
+    /// Proofs per chain re-sent from the sent-log when the aggregator comes back after
+    /// failed deliveries or reports a new session, 0 disables resending
+    pub resend_on_reconnect: usize,
+    /// ZMQ endpoint by chain name, taking precedence over `endpoint_template`
+    pub endpoints: HashMap<String, String>,
+    /// ZMQ endpoint of chains not in `endpoints`, `{chain}` being replaced by the chain
+    /// name; unset sends them to `ZMQ_CHANNEL_URL`
+    pub endpoint_template: Option<String>,
+    /// How proofs are spread over the endpoints when `ZMQ_CHANNEL_URL` lists several
+    pub endpoint_mode: EndpointMode,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            queue_capacity: 100,
+            max_in_flight: 1,
+            batch_size: 1,
+            recv_timeout_ms: 20000,
+            retries: 0,
+            send_retries: 3,
+            backoff_base_ms: 500,
+            backoff_max_ms: 10000,
+            ack_mode: AckMode::Wait,
+            ordering: HashMap::new(),
+            resend_on_reconnect: 0,
+            endpoints: HashMap::new(),
+            endpoint_template: None,
+            endpoint_mode: EndpointMode::default(),
+        }
+    }
+}
+
+/// The `Authorization` value of a bearer token, `None` for a blank token so no bare
+/// `Bearer ` header is sent
+pub fn bearer_auth(token: &str) -> Option<String> {
+    let token = token.trim();
+    (!token.is_empty()).then(|| format!("Bearer {}", token))
+}
+
+/// Checks the shape of an endpoint to connect to, e.g. `tcp://aggregator:40006`
+fn validate_zmq_endpoint(endpoint: &str) -> std::result::Result<(), String> {
+    let (transport, address) = endpoint
+        .split_once("://")
+        .ok_or("expected <transport>://<address>")?;
+    if address.is_empty() {
+        return Err("missing address".to_string());
+    }
+    match transport {
+        "tcp" => {
+            let (host, port) = address.rsplit_once(':').ok_or("missing port")?;
+            if host.is_empty() {
+                return Err("missing host".to_string());
+            }
+            port.parse::<u16>()
+                .map(|_| ())
+                .map_err(|_| format!("invalid port '{}'", port))
+        }
+        "ipc" | "inproc" | "pgm" | "epgm" => Ok(()),
+        _ => Err(format!("unsupported transport '{}'", transport)),
+    }
+}
+
+/// Upper bound of `SinkConfig::batch_size`
+pub const MAX_BATCH_SIZE: usize = 100;
+
+impl SinkConfig {
+    /// Backoff before retry number `attempt` (starting at 0)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff_base_ms
+            .saturating_mul(2u64.saturating_pow(attempt))
+            .min(self.backoff_max_ms);
+        Duration::from_millis(delay)
+    }
+
+    /// The ZMQ endpoint configured for `chain`, `None` for the global `ZMQ_CHANNEL_URL`
+    pub fn zmq_endpoint(&self, chain: &str) -> Option<String> {
+        self.endpoints.get(chain).cloned().or_else(|| {
+            self.endpoint_template
+                .as_ref()
+                .map(|template| template.replace("{chain}", chain))
+        })
+    }
+
+    /// Fails unless the endpoint each of `chains` resolves to is one ZMQ can connect to
+    pub fn check_zmq_endpoints<'a>(&self, chains: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        for chain in chains {
+            if let Some(endpoint) = self.zmq_endpoint(chain) {
+                validate_zmq_endpoint(&endpoint).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid ZMQ endpoint '{}' of {}: {}", endpoint, chain, e),
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured batch size bounded to `1..=MAX_BATCH_SIZE`
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.clamp(1, MAX_BATCH_SIZE)
+    }
+}
+
+/// Concurrency limits for chains sharing an RPC provider
+///
+/// Chains are grouped by the host of their RPC url unless `groups` assigns them
+/// explicitly. Groups without an entry in `limits` are unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProvidersConfig {
+    /// Maximum concurrent requests per provider group
+    pub limits: HashMap<String, usize>,
+    /// Chain name to provider group
+    pub groups: HashMap<String, String>,
+}
+
+/// A contract event declared in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventConfig {
+    pub name: String,
+    /// Canonical signature without names or spaces, e.g. `RootPosted(bytes,bytes32)`
+    pub signature: String,
+    /// Positions of the indexed params
+    #[serde(default)]
+    pub indexed: Vec<usize>,
+    /// Position of the `bytes32` param forwarded as the root
+    pub root_param: usize,
+    /// Position of a `uint` param holding the L2 block number, forwarded with the root
+    pub block_number_param: Option<usize>,
+    /// Describes the forwarded payload in logs, defaults to `"<name> data"`
+    pub label: Option<String>,
+}
+
+/// Catch-up pacing for contract chains that fell behind, e.g. after downtime
+///
+/// While a chain lags its head by more than `catch_up_lag_blocks` the loop runs every
+/// `catch_up_interval_secs` with the larger catch-up block budget, once within the lag
+/// it relaxes back to `PROOF_COLLECTION_INTERVAL` and the normal budget.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PacingConfig {
+    /// Lag in blocks above which a chain is catching up, unset disables catch-up pacing
+    pub catch_up_lag_blocks: Option<u64>,
+    /// Seconds between cycles while any chain is catching up
+    pub catch_up_interval_secs: u64,
+    /// Maximum blocks scanned per cycle, unset scans up to the head
+    pub block_budget: Option<u64>,
+    /// Maximum blocks scanned per cycle while catching up, unset scans up to the head
+    pub catch_up_block_budget: Option<u64>,
+    /// Blocks per `eth_getLogs` query, longer ranges are queried window by window
+    pub log_window_blocks: u64,
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        PacingConfig {
+            catch_up_lag_blocks: None,
+            catch_up_interval_secs: 30,
+            block_budget: None,
+            catch_up_block_budget: None,
+            log_window_blocks: 2000,
+        }
+    }
+}
+
+/// Retry policy of a JSON-RPC call, `max_attempts` counting the first one
+///
+/// Attempts are spaced by an exponential backoff from `base_delay_ms` up to
+/// `max_delay_ms`, each delay randomly shortened by up to half to spread retries out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Timeout of each attempt, `RPC_TIMEOUT_SECS` when unset
+    pub timeout_ms: Option<u64>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 250,
+            max_delay_ms: 5000,
+            timeout_ms: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// A single attempt, for one-off calls such as `probe`
+    pub fn once() -> Self {
+        RetryConfig {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (starting at 0), with jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(retry))
+            .min(self.max_delay_ms);
+        // The clock's sub-second nanos are random enough to decorrelate retries
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.subsec_nanos() as u64)
+            .unwrap_or_default();
+        Duration::from_millis(delay - nanos % (delay / 2 + 1))
+    }
+}
+
+/// Rotation of the segment files written by the `segment:` sink
+///
+/// A segment is closed and a new one started once it reaches `max_bytes` or is older
+/// than `max_age_secs`, whichever comes first.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SegmentConfig {
+    pub max_bytes: u64,
+    pub max_age_secs: u64,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        SegmentConfig {
+            max_bytes: 64 * 1024 * 1024,
+            max_age_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Token buckets applied to a REST route, unset buckets don't limit
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Shared by all clients
+    pub global: Option<BucketConfig>,
+    /// One bucket per client IP address
+    pub per_ip: Option<BucketConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BucketConfig {
+    /// Tokens added per second, each request takes one
+    pub rate_per_sec: f64,
+    /// Bucket size, the number of requests allowed in a burst
+    pub burst: u32,
+}
+
+/// Extra HTTP headers of a chain's RPC requests, e.g. an `X-API-Key`
+///
+/// Values often hold secrets, so `Debug` and `Serialize` only show the header names.
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct RpcHeaders(pub BTreeMap<String, String>);
+
+impl RpcHeaders {
+    fn redacted(&self) -> BTreeMap<&str, &str> {
+        self.0.keys().map(|name| (name.as_str(), "<redacted>")).collect()
+    }
+}
+
+impl fmt::Debug for RpcHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.redacted().fmt(f)
+    }
+}
+
+impl Serialize for RpcHeaders {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.redacted().serialize(serializer)
+    }
+}
+
+/// How a chain is read, written `sdk`, `rpc` or `contract` in config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainKind {
+    /// Avail through its SDK client
+    Sdk,
+    /// The head of a JSON-RPC node
+    #[default]
+    Rpc,
+    /// Events emitted by a contract
+    Contract,
+}
+
+/// A chain polled by the loop
+#[derive(Debug, Clone, Default)]
+pub struct ChainConfig {
+    pub kind: ChainKind,
+    pub name: String,
+    pub chain_id: i32,
+    pub rpc_url: String,
+    /// Endpoints tried in order after `rpc_url` when it can't be reached
+    pub fallback_rpc_urls: Vec<String>,
+    /// WebSocket endpoint whose `newHeads` subscription replaces polling while it is live
+    pub ws_url: Option<String>,
+    pub method: String,
+    pub contract_address: String,
+    /// Contracts watched besides `contract_address`, e.g. further verifiers of a rollup
+    pub extra_contract_addresses: Vec<String>,
+    /// Events scanned on contract chains
+    pub events: Vec<EventSpec>,
+    /// Value of the `Authorization` header sent with RPC requests
+    pub auth: Option<String>,
+    /// Extra headers sent with RPC requests
+    pub headers: RpcHeaders,
+    /// Expected seconds between blocks, used to decide when an unchanged head is stale
+    pub expected_block_time: Option<u64>,
+    /// Forward the EIP-4844 `blobGasUsed`/`excessBlobGas` fields with the block hash
+    pub blob_fields: bool,
+    /// Blocks below the head a contract chain's events must be before they are scanned,
+    /// or an RPC chain's block before its hash is forwarded
+    pub confirmations: u64,
+    /// Delay after the cycle start before the chain is read, to stagger chains
+    pub offset: Duration,
+    /// Re-fetch each matched log's receipt and check it's still included before forwarding
+    pub verify_receipt: bool,
+    /// Forward an unchanged head only every this many cycles, as a keepalive
+    pub keepalive_cycles: Option<u64>,
+    /// Retries of the chain's JSON-RPC calls
+    pub rpc_retry: RetryConfig,
+    /// Recent blocks checked for a reorg, unset disables reorg detection
+    pub reorg_depth: Option<u64>,
+}
+
+impl ChainConfig {
+    pub fn sdk(name: &str, chain_id: i32) -> Self {
+        ChainConfig {
+            kind: ChainKind::Sdk,
+            name: name.to_string(),
+            chain_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn rpc(name: &str, chain_id: i32, rpc_url: &str, method: &str) -> Self {
+        ChainConfig {
+            kind: ChainKind::Rpc,
+            name: name.to_string(),
+            chain_id,
+            rpc_url: rpc_url.to_string(),
+            method: method.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A contract chain, its events are added with `with_event`
+    pub fn contract(name: &str, chain_id: i32, rpc_url: &str, contract_address: &str) -> Self {
+        ChainConfig {
+            kind: ChainKind::Contract,
+            name: name.to_string(),
+            chain_id,
+            rpc_url: rpc_url.to_string(),
+            contract_address: contract_address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Uses the first of `urls` as the RPC url and the rest as its fallbacks
+    pub fn with_rpc_urls(mut self, urls: &[String]) -> Self {
+        if let Some((primary, fallbacks)) = urls.split_first() {
+            self.rpc_url = primary.clone();
+            self.fallback_rpc_urls = fallbacks.to_vec();
+        }
+        self
+    }
+
+    /// The contract address followed by the extra ones
+    pub fn contract_addresses(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.contract_address.as_str())
+            .chain(self.extra_contract_addresses.iter().map(String::as_str))
+    }
+
+    /// The RPC url followed by its fallbacks
+    pub fn rpc_urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.rpc_url.as_str()).chain(self.fallback_rpc_urls.iter().map(String::as_str))
+    }
+
+    /// Sets the `Authorization` header of the chain's RPC requests
+    pub fn with_auth(mut self, auth: Option<&str>) -> Self {
+        self.auth = auth.map(String::from);
+        self
+    }
+
+    pub fn with_expected_block_time(mut self, seconds: u64) -> Self {
+        self.expected_block_time = Some(seconds);
+        self
+    }
+
+    pub fn with_event(mut self, event: EventSpec) -> Self {
+        self.events.push(event);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_auth_sends_no_header() {
+        assert_eq!(bearer_auth(""), None);
+        assert_eq!(bearer_auth("  \n"), None);
+        assert_eq!(bearer_auth(" secret ").as_deref(), Some("Bearer secret"));
+
+        let chain = ChainConfig::rpc("celestia", 0, "http://localhost:26658", "header.NetworkHead")
+            .with_auth(bearer_auth("").as_deref());
+        assert_eq!(chain.auth, None);
+    }
+}
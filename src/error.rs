@@ -0,0 +1,62 @@
+use ethers::providers::ProviderError;
+use thiserror::Error;
+
+use crate::rpc_call::rpc::JsonRpcError;
+
+/// Failures of the `BlockReader` fetch paths, by where they come from
+///
+/// Proofs are handed to the sink task over a channel, so ZMQ errors surface in the sink
+/// and its metrics rather than here.
+#[derive(Debug, Error)]
+pub enum BlockReaderError {
+    /// A JSON-RPC call of an RPC chain failed to get an answer
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    /// The node answered a JSON-RPC call with an error object
+    #[error("JSON-RPC error {code}: {message}")]
+    JsonRpc { code: i64, message: String },
+    /// A call to the Avail node through its SDK failed
+    #[error("Avail error: {0}")]
+    Avail(String),
+    /// An `ethers` call of a contract chain failed
+    #[error("provider error: {0}")]
+    Provider(#[from] ProviderError),
+    /// An event or proof couldn't be ABI decoded or encoded
+    #[error("ABI error: {0}")]
+    Abi(#[from] ethabi::Error),
+    /// Reading or writing the state under `block_numbers/` failed
+    #[error("state file error: {0}")]
+    State(#[from] std::io::Error),
+    /// The node answered with data that can't be forwarded, e.g. a missing block or a
+    /// hash that doesn't belong to the requested number
+    #[error("{0}")]
+    InvalidData(String),
+}
+
+impl BlockReaderError {
+    /// Wraps an `rpc_call` error, keeping JSON-RPC error objects apart from transport
+    /// failures
+    pub fn from_rpc(error: Box<dyn std::error::Error>) -> Self {
+        match error.downcast::<JsonRpcError>() {
+            Ok(error) => BlockReaderError::JsonRpc {
+                code: error.code,
+                message: error.message,
+            },
+            Err(error) => BlockReaderError::Rpc(error.to_string()),
+        }
+    }
+}
+
+impl From<avail_rust_client::Error> for BlockReaderError {
+    fn from(error: avail_rust_client::Error) -> Self {
+        BlockReaderError::Avail(error.to_string())
+    }
+}
+
+impl From<avail_rust_client::RpcError> for BlockReaderError {
+    fn from(error: avail_rust_client::RpcError) -> Self {
+        BlockReaderError::Avail(error.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, BlockReaderError>;
@@ -0,0 +1,395 @@
+use ethabi::{decode, param_type::Reader, ParamType, Token};
+use ethers::core::types::{Address, Log, H256, U256};
+
+use crate::{config::EventConfig, util::debug_enabled};
+
+/// A decoded `VerifyBatchesTrustedAggregator` event
+///
+/// Solidity: `VerifyBatchesTrustedAggregator(uint32 indexed rollupID, uint64 numBatch,
+/// bytes32 stateRoot, bytes32 exitRoot, address indexed aggregator)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyBatchesEvent {
+    pub rollup_id: u32,
+    pub num_batch: u64,
+    pub state_root: H256,
+    pub exit_root: H256,
+    pub aggregator: Address,
+}
+
+impl VerifyBatchesEvent {
+    /// Decodes the indexed params from the topics and the rest from the ABI encoded data
+    pub fn decode(log: &Log) -> Result<Self, String> {
+        if log.topics.len() < 3 {
+            return Err(format!("expected 3 topics, found {}", log.topics.len()));
+        }
+
+        // rollupID (topic1) and aggregator (topic2) are indexed, left padded to 32 bytes
+        let rollup_id = U256::from_big_endian(log.topics[1].as_bytes());
+        if rollup_id > U256::from(u32::MAX) {
+            return Err(format!("rollupID {} does not fit in uint32", rollup_id));
+        }
+        let aggregator = Address::from_slice(&log.topics[2].as_bytes()[12..]);
+
+        let tokens = decode(
+            &[
+                ParamType::Uint(64),
+                ParamType::FixedBytes(32),
+                ParamType::FixedBytes(32),
+            ],
+            &log.data,
+        )
+        .map_err(|e| format!("invalid event data: {}", e))?;
+
+        match tokens.as_slice() {
+            [Token::Uint(num_batch), Token::FixedBytes(state_root), Token::FixedBytes(exit_root)] => {
+                Ok(VerifyBatchesEvent {
+                    rollup_id: rollup_id.low_u32(),
+                    num_batch: num_batch.low_u64(),
+                    state_root: H256::from_slice(state_root),
+                    exit_root: H256::from_slice(exit_root),
+                    aggregator,
+                })
+            }
+            _ => Err("unexpected event data layout".to_string()),
+        }
+    }
+}
+
+/// Where the forwarded root of a matched log is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootSource {
+    /// An indexed param, `Topic(1)` being the first one
+    Topic(usize),
+    /// A `bytes32` among the non-indexed params ABI encoded in the log data
+    ///
+    /// `params` lists the types of every non-indexed param in order, dynamic ones such
+    /// as `bytes` or `string` included, since they shift the data through the head/tail
+    /// layout. `index` is the root's position among them.
+    Data { params: Vec<ParamType>, index: usize },
+    /// The `stateRoot` of a decoded `VerifyBatchesTrustedAggregator`, optionally of one rollup only
+    VerifyBatchesStateRoot { rollup_id: Option<u32> },
+}
+
+/// Where the block number forwarded with the root is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumberSource {
+    /// An indexed param, `Topic(1)` being the first one
+    Topic(usize),
+    /// A `uint` among the non-indexed params, laid out like `RootSource::Data`
+    Data { params: Vec<ParamType>, index: usize },
+}
+
+/// An event watched on a contract chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventSpec {
+    /// Event name, used in logs and to key the event's dedup state
+    pub name: String,
+    /// Canonical signature hashed into topic0, e.g. `L2MerkleRootAdded(bytes32,uint256)`
+    pub signature: String,
+    pub root_source: RootSource,
+    /// The L2 block number the root belongs to, forwarded with the root when set
+    pub block_number_source: Option<NumberSource>,
+    /// Describes the forwarded payload in logs, e.g. "L2MerkleRoot data"
+    pub label: String,
+}
+
+impl EventSpec {
+    /// Linea's `L2MerkleRootAdded(bytes32 indexed merkleRoot, uint256 indexed blockNumber)`
+    pub fn l2_merkle_root_added() -> Self {
+        EventSpec {
+            name: "L2MerkleRootAdded".to_string(),
+            signature: "L2MerkleRootAdded(bytes32,uint256)".to_string(),
+            root_source: RootSource::Topic(1),
+            block_number_source: Some(NumberSource::Topic(2)),
+            label: "L2MerkleRoot data".to_string(),
+        }
+    }
+
+    /// The rollup manager's `VerifyBatchesTrustedAggregator`, `rollup_id` limits it to one rollup
+    pub fn verify_batches_trusted_aggregator(rollup_id: Option<u32>) -> Self {
+        EventSpec {
+            name: "VerifyBatchesTrustedAggregator".to_string(),
+            signature: "VerifyBatchesTrustedAggregator(uint32,uint64,bytes32,bytes32,address)"
+                .to_string(),
+            root_source: RootSource::VerifyBatchesStateRoot { rollup_id },
+            block_number_source: None,
+            label: "VerifyBatchesTrustedAggregator data".to_string(),
+        }
+    }
+
+    /// Builds an event declared in the config file
+    ///
+    /// The root param is read from the topics when it's indexed and decoded from the
+    /// log data otherwise.
+    pub fn from_config(config: &EventConfig) -> Result<Self, String> {
+        let param_types = signature_param_types(&config.signature)?;
+        if config.root_param >= param_types.len() {
+            return Err(format!(
+                "root_param {} out of range for {}",
+                config.root_param, config.signature
+            ));
+        }
+
+        if let Some(block_number_param) = config.block_number_param {
+            if block_number_param >= param_types.len() {
+                return Err(format!(
+                    "block_number_param {} out of range for {}",
+                    block_number_param, config.signature
+                ));
+            }
+        }
+
+        // topic0 is the signature, indexed params follow in declaration order
+        let topic_position =
+            |param: usize| config.indexed.iter().filter(|&&i| i < param).count() + 1;
+        let data_params: Vec<(usize, ParamType)> = param_types
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !config.indexed.contains(i))
+            .collect();
+        let data_position = |param: usize| {
+            data_params
+                .iter()
+                .position(|(i, _)| *i == param)
+                .unwrap_or_default()
+        };
+        let data_types: Vec<ParamType> = data_params.iter().map(|(_, param)| param.clone()).collect();
+
+        let root_source = if config.indexed.contains(&config.root_param) {
+            RootSource::Topic(topic_position(config.root_param))
+        } else {
+            RootSource::Data {
+                index: data_position(config.root_param),
+                params: data_types.clone(),
+            }
+        };
+        let block_number_source = config.block_number_param.map(|param| {
+            if config.indexed.contains(&param) {
+                NumberSource::Topic(topic_position(param))
+            } else {
+                NumberSource::Data {
+                    index: data_position(param),
+                    params: data_types.clone(),
+                }
+            }
+        });
+
+        Ok(EventSpec {
+            name: config.name.clone(),
+            signature: config.signature.clone(),
+            root_source,
+            block_number_source,
+            label: config
+                .label
+                .clone()
+                .unwrap_or_else(|| format!("{} data", config.name)),
+        })
+    }
+
+    pub fn topic0(&self) -> H256 {
+        crate::block_reader::event_topic(&self.signature)
+    }
+
+    /// Indexed topic1 value the node can filter on, e.g. the rollupID
+    pub fn topic1_filter(&self) -> Option<H256> {
+        match self.root_source {
+            RootSource::VerifyBatchesStateRoot {
+                rollup_id: Some(rollup_id),
+            } => Some(H256::from_low_u64_be(rollup_id.into())),
+            _ => None,
+        }
+    }
+
+    /// Extracts the L2 block number forwarded with the root, `None` when the event has none
+    pub fn extract_block_number(&self, log: &Log) -> Result<Option<U256>, String> {
+        match &self.block_number_source {
+            None => Ok(None),
+            Some(NumberSource::Topic(index)) => log
+                .topics
+                .get(*index)
+                .map(|topic| Some(U256::from_big_endian(topic.as_bytes())))
+                .ok_or_else(|| format!("no topic {} in event", index)),
+            Some(NumberSource::Data { params, index }) => {
+                let tokens =
+                    decode(params, &log.data).map_err(|e| format!("invalid event data: {}", e))?;
+                match tokens.get(*index) {
+                    Some(Token::Uint(number)) => {
+                        let mut bytes = [0u8; 32];
+                        number.to_big_endian(&mut bytes);
+                        Ok(Some(U256::from_big_endian(&bytes)))
+                    }
+                    Some(token) => Err(format!("param {} is not a uint: {:?}", index, token)),
+                    None => Err(format!("no param {} in event data", index)),
+                }
+            }
+        }
+    }
+
+    /// Extracts the root to forward from a log of this event
+    ///
+    /// Errors describe why the log is skipped: it can't be decoded, belongs to
+    /// another rollup or carries an all zero state root.
+    pub fn extract_root(&self, log: &Log) -> Result<H256, String> {
+        match self.root_source {
+            RootSource::Topic(index) => log
+                .topics
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("no topic {} in event", index)),
+            RootSource::Data { ref params, index } => {
+                let tokens =
+                    decode(params, &log.data).map_err(|e| format!("invalid event data: {}", e))?;
+                match tokens.get(index) {
+                    Some(Token::FixedBytes(root)) if root.len() == 32 => Ok(H256::from_slice(root)),
+                    Some(token) => Err(format!("param {} is not a bytes32: {:?}", index, token)),
+                    None => Err(format!("no param {} in event data", index)),
+                }
+            }
+            RootSource::VerifyBatchesStateRoot { rollup_id } => {
+                let event = VerifyBatchesEvent::decode(log)?;
+                if debug_enabled() {
+                    eprintln!("Decoded event: {:?}", event);
+                }
+
+                // The provider may have ignored the topic1 filter
+                if let Some(rollup_id) = rollup_id {
+                    if event.rollup_id != rollup_id {
+                        return Err(format!(
+                            "event is for rollup {}, expected {}",
+                            event.rollup_id, rollup_id
+                        ));
+                    }
+                }
+                if event.state_root == H256::zero() {
+                    return Err("state root is all zeros (0x0000...00)".to_string());
+                }
+                Ok(event.state_root)
+            }
+        }
+    }
+}
+
+/// Parses the param types of a canonical event signature, e.g. `Posted(bytes,bytes32)`
+///
+/// Tuple params such as `(uint256,bytes)` are kept whole.
+fn signature_param_types(signature: &str) -> Result<Vec<ParamType>, String> {
+    let params = signature
+        .split_once('(')
+        .and_then(|(_, rest)| rest.strip_suffix(')'))
+        .ok_or_else(|| format!("invalid event signature '{}'", signature))?;
+    if params.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Split on the commas outside of tuples
+    let mut types = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    types.push(&params[start..]);
+
+    types
+        .into_iter()
+        .map(|param| {
+            Reader::read(param.trim())
+                .map_err(|e| format!("invalid param type '{}' in '{}': {}", param, signature, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::encode;
+
+    fn log(topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            topics,
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Data of a `VerifyBatchesTrustedAggregator` log as the rollup manager emits it:
+    /// `numBatch`, `stateRoot` and `exitRoot` as three words
+    const VERIFY_BATCHES_DATA: &str = concat!(
+        "000000000000000000000000000000000000000000000000000000000001e1f7",
+        "6b3e8e1f2a4c5d7e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f6a",
+        "3d7a1e9c5b2f4a8d6e0c7b9a1f3e5d2c4b6a8f0e1d3c5b7a9f2e4d6c8b0a1f3e",
+    );
+
+    fn verify_batches_log(data: &str) -> Log {
+        let spec = EventSpec::verify_batches_trusted_aggregator(None);
+        log(
+            vec![
+                spec.topic0(),
+                // rollupID 1
+                "0x0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+                // aggregator
+                "0x00000000000000000000000020a53dcb196cd2bcf66a3bf4f13be9d2d0a45af9".parse().unwrap(),
+            ],
+            avail_rust_client::ext::const_hex::decode(data).unwrap(),
+        )
+    }
+
+    #[test]
+    fn decodes_verify_batches_log() {
+        let log = verify_batches_log(VERIFY_BATCHES_DATA);
+        assert_eq!(
+            VerifyBatchesEvent::decode(&log),
+            Ok(VerifyBatchesEvent {
+                rollup_id: 1,
+                num_batch: 123383,
+                state_root: "0x6b3e8e1f2a4c5d7e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f6a".parse().unwrap(),
+                exit_root: "0x3d7a1e9c5b2f4a8d6e0c7b9a1f3e5d2c4b6a8f0e1d3c5b7a9f2e4d6c8b0a1f3e".parse().unwrap(),
+                aggregator: "0x20a53dcb196cd2bcf66a3bf4f13be9d2d0a45af9".parse().unwrap(),
+            })
+        );
+
+        let state_root = VerifyBatchesEvent::decode(&log).unwrap().state_root;
+        assert_eq!(EventSpec::verify_batches_trusted_aggregator(Some(1)).extract_root(&log), Ok(state_root));
+        assert!(EventSpec::verify_batches_trusted_aggregator(Some(2)).extract_root(&log).is_err());
+
+        let zero_root = format!("{}{}{}", &VERIFY_BATCHES_DATA[..64], "0".repeat(64), &VERIFY_BATCHES_DATA[128..]);
+        assert!(EventSpec::verify_batches_trusted_aggregator(None)
+            .extract_root(&verify_batches_log(&zero_root))
+            .is_err());
+        assert!(VerifyBatchesEvent::decode(&verify_batches_log(&VERIFY_BATCHES_DATA[..128])).is_err());
+    }
+
+    #[test]
+    fn decodes_root_after_dynamic_bytes() {
+        let spec = EventSpec::from_config(&EventConfig {
+            name: "RootPosted".to_string(),
+            signature: "RootPosted(bytes,bytes32,uint256)".to_string(),
+            indexed: vec![],
+            root_param: 1,
+            block_number_param: Some(2),
+            label: None,
+        })
+        .unwrap();
+        let root = H256::repeat_byte(0xab);
+        // 40 bytes of calldata take two words in the tail, after the 3 word head
+        let data = encode(&[
+            Token::Bytes(vec![0x11; 40]),
+            Token::FixedBytes(root.as_bytes().to_vec()),
+            Token::Uint(77.into()),
+        ]);
+        assert_eq!(data.len(), 32 * 3 + 32 + 64);
+        // The head holds the offset of the bytes, not the bytes themselves
+        assert_eq!(U256::from_big_endian(&data[..32]), U256::from(96));
+
+        let log = log(vec![spec.topic0()], data);
+        assert_eq!(spec.extract_root(&log), Ok(root));
+        assert_eq!(spec.extract_block_number(&log), Ok(Some(U256::from(77))));
+    }
+}
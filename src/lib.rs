@@ -0,0 +1,44 @@
+//! Reads block hashes and contract events of the configured chains and forwards them
+//! as ABI encoded proofs.
+//!
+//! The `avail-block-reader-rust` binary is a thin wrapper around this library, which can
+//! also be embedded in another service or driven from integration tests:
+//!
+//! - [`BlockReader`] fetches heads over the Avail SDK, JSON-RPC or contract logs and
+//!   queues the resulting [`ProofEvent`]s on a channel, see
+//!   [`BlockReader::block_hash_from_rpc`], [`BlockReader::fetch_block_hash`] and
+//!   [`BlockReader::read_contract_events`].
+//! - [`BlockReader::run_loop`] reads a list of chains every interval, see
+//!   [`run_loop::loop_chains`] for the chains of the binary's loop mode.
+//! - [`spawn_sink`] drains that channel into a [`ProofSink`] such as [`ZmqSink`] or
+//!   [`JsonlSink`].
+//! - [`Router`] maps REST requests to async handlers, [`rest::rest_server`] serves the
+//!   binary's REST API with it.
+//! - [`block_number_op`] and [`merkle_root_op`] persist the last processed block numbers
+//!   and merkle roots per chain under `block_numbers/`.
+
+pub mod block_number_op;
+pub mod block_reader;
+pub mod config;
+pub mod error;
+pub mod events;
+pub mod merkle_root_op;
+pub mod metrics;
+pub mod pending;
+pub mod provider_limit;
+pub mod rate_limit;
+pub mod rest;
+pub mod router;
+pub mod rpc_call;
+pub mod run_loop;
+pub mod segment_log;
+pub mod sent_log;
+pub mod sink;
+pub mod util;
+pub mod websocket;
+
+pub use block_reader::BlockReader;
+pub use config::{ChainConfig, Config};
+pub use error::BlockReaderError;
+pub use router::Router;
+pub use sink::{spawn_sink, JsonlSink, ProofEvent, ProofSink, ZmqSink};
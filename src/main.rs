@@ -1,342 +1,423 @@
-use avail_rust_client::{ext::const_hex, H256};
+use avail_rust_client::H256;
 use clap::Parser;
 use ethers::core::types::Address;
 use std::{
+    collections::BTreeMap,
     fs,
     io::{Error, ErrorKind, Result},
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    time::sleep,
+    time::Duration,
 };
+use tokio::sync::{broadcast, mpsc};
 
-mod block_number_op;
-mod block_reader;
 mod cli_args;
-mod merkle_root_op;
-mod router;
-mod rpc_call;
-mod util;
 
-use block_number_op::{read_block_number, write_block_number};
-use block_reader::BlockReader;
-use cli_args::{Args, Mode};
-use router::Router;
+use avail_block_reader_rust::{
+    block_number_op::state_dir,
+    block_reader::{event_topic, BlockReader},
+    config::{ChainConfig, Config, RetryConfig},
+    rest::rest_server,
+    rpc_call::rpc::{rpc_call, rpc_call_batch},
+    run_loop::{loop_chains, LoopOptions, LOOP_CHAINS},
+    segment_log::{read_segment, segment_files, SegmentSink},
+    sent_log::read_sent_log,
+    sink::{spawn_sink, JsonlSink, ProofEvent, ProofSink, UnixSink, ZmqSink},
+    util::{decode_block_hash, get_rpc_call_params, read_rpc_response},
+    websocket::tail_forwards,
+};
+use cli_args::{Args, Command, Mode, SinkKind};
+use serde_json::json;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     let args = Args::parse();
 
-    fs::create_dir_all("block_numbers")?;
+    match &args.command {
+        Some(Command::Topic { signature }) => {
+            println!("{:?}", event_topic(signature));
+            return Ok(());
+        }
+        Some(Command::Probe {
+            url,
+            methods,
+            chain,
+            auth,
+            headers,
+            hash_pointer,
+            number_pointer,
+        }) => {
+            return probe_rpc(
+                url,
+                methods,
+                chain,
+                auth.as_deref(),
+                headers,
+                hash_pointer.as_deref(),
+                number_pointer.as_deref(),
+            )
+            .await;
+        }
+        Some(Command::Tail { url }) => {
+            tail_forwards(url).await;
+            return Ok(());
+        }
+        Some(Command::Replay {
+            segment,
+            from_audit,
+            chain,
+            since,
+        }) => {
+            let proofs = match (segment, from_audit) {
+                (Some(segment), _) => {
+                    if matches!(&args.sink, SinkKind::Segment(dir) if segment.starts_with(dir)) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "refusing to replay segments into the segment directory they are read from",
+                        ));
+                    }
+                    segment_proofs(segment, chain.as_deref(), *since)?
+                }
+                (None, Some(sent_log)) => sent_log_proofs(sent_log, chain.as_deref(), *since)?,
+                (None, None) => unreachable!("clap requires a segment or --from-audit"),
+            };
+            let config = Config::load(args.config.as_deref())?;
+            let sink = build_sink(&args, &config)?;
+            return replay_proofs(sink, proofs).await;
+        }
+        None => {}
+    }
+
+    // Chain ids end up in every proof, refuse to start without the ones this mode needs
+    if matches!(args.mode, Mode::REST | Mode::BOTH) {
+        rest_chain(&args)?;
+    }
+    if matches!(args.mode, Mode::LOOP | Mode::BOTH) {
+        required_chain_id(args.avail_chain_id, "avail")?;
+    }
 
-    let br = Arc::new(BlockReader::new());
+    fs::create_dir_all(state_dir())?;
 
-        match args.mode {
-        Mode::TEST => {
-            br.read_latest_l2_merkle_root_event(
+    let config = Config::load(args.config.as_deref())?;
+    if matches!(args.sink, SinkKind::Zmq) && matches!(args.mode, Mode::REST | Mode::BOTH) {
+        config.sink.check_zmq_endpoints([rest_chain(&args)?.name.as_str()])?;
+    }
+    let (proof_sender, proof_receiver) = mpsc::channel(config.sink.queue_capacity.max(1));
+    // Every delivery is published here for the `/ws` subscribers
+    let (on_forward, _) = broadcast::channel(100);
+    let sink = build_sink(&args, &config)?;
+    let sink_task = spawn_sink(sink.clone(), &config.sink, on_forward.clone(), proof_receiver);
+    let max_concurrency = args.max_concurrency.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(4, |cpus| cpus.get())
+    });
+    let avail_rpc_url = args.avail_rpc_url.trim();
+    if avail_rpc_url.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "AVAIL_RPC_URL must not be empty"));
+    }
+    eprintln!("Reading Avail from {}", avail_rpc_url);
+    let br = Arc::new(BlockReader::new(
+        proof_sender,
+        avail_rpc_url,
+        &config.providers,
+        max_concurrency,
+        config.pacing.clone(),
+    ));
+
+    let result = match args.mode {
+        Mode::TEST => br
+            .read_latest_l2_merkle_root_event(
                 "https://0xrpc.io/eth",
                 "0xd19d4B5d358258f05D7B411E21A1460D11B0876F".parse::<Address>().unwrap(),
                 59144,
-                "linea"
-            ).await.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+                "linea",
+            )
+            .await
+            .map_err(Error::other),
+        Mode::REST => {
+            rest_server(br.clone(), rest_chain(&args)?, config.add_block_rate_limit.clone(), sink, on_forward).await
+        }
+        Mode::LOOP => {
+            let (chains, options) = loop_setup(&args, &config)?;
+            br.clone().run_loop(chains, options).await
         }
-        Mode::REST => rest_server(br.clone()).await?,
-        Mode::LOOP => iterate_block_reader(br.clone()).await?,
         Mode::BOTH => {
-            if let Err(e) = tokio::try_join!(
-                rest_server(br.clone()),
-                iterate_block_reader(br.clone()),
-            ) {
+            let rest_chain = rest_chain(&args)?;
+            let (chains, options) = loop_setup(&args, &config)?;
+            // The REST server runs until the loop ends, e.g. after `--max-cycles`
+            let result = tokio::select! {
+                result = rest_server(br.clone(), rest_chain, config.add_block_rate_limit.clone(), sink, on_forward) => result,
+                result = br.clone().run_loop(chains, options) => result,
+            };
+            if let Err(e) = &result {
                 eprintln!("Error in BOTH mode: {}", e);
             }
+            result
         }
+    };
+
+    // Let the sink deliver the proofs that are still queued before exiting
+    drop(br);
+    if let Err(e) = sink_task.await {
+        eprintln!("Sink task failed: {}", e);
     }
 
-    Ok(())
+    result
 }
 
-async fn iterate_block_reader(br: Arc<BlockReader>) -> Result<()> {
-    let proof_collection_interval =
-            std::env::var("PROOF_COLLECTION_INTERVAL").unwrap_or_else(|_| "600".to_string()).parse::<u64>().unwrap();
-    let mut last_block_hash: Option<H256> = None;
-    let celestia_rpc_auth =
-            std::env::var("CELESTIA_RPC_AUTH").unwrap_or_else(|_| "".to_string());
-    let celestia_auth_string = format!("Bearer {}", celestia_rpc_auth);
-    let celestia_rpc_auth_param = Some(celestia_auth_string.as_str());
-    let celestia_rpc_url = std::env::var("CELESTIA_RPC_URL").unwrap_or_else(|_| "http://localhost:26658".to_string());
-    let eth_rpc_url = std::env::var("ETH_RPC_URL").unwrap_or_else(|_| "https://0xrpc.io/eth".to_string());
-    let bsc_rpc_url = std::env::var("BSC_RPC_URL").unwrap_or_else(|_| "https://bsc-rpc.publicnode.com".to_string());
-    let arbi_rpc_url = std::env::var("ARBI_RPC_URL").unwrap_or_else(|_| "https://arb1.arbitrum.io/rpc".to_string());
-    let block_fetch_params: Vec<(&str, &str, i32, &str, &str, &str, Option<&str>, Option<&str>)> = vec![
-        (
-            "sdk",
-            "avail",
-            1000,
-            "",
-            "",
-            "",
-            None,
-            None,
-        ), // Avail chain ID - update this to the correct value
-        (
-            "rpc",
-            "onlylayer",
-            5820948,
-            "https://onlylayer.org",
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-        (
-            "rpc",
-            "mintchain",
-            185,
-            "https://global.rpc.mintchain.io",
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-        (
-            "rpc",
-            "u2u",
-            39,
-            "https://rpc-mainnet.u2u.xyz",
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-        (
-            "rpc",
-            "celestia",
-            131415,
-            &celestia_rpc_url.as_str(),
-            "header.NetworkHead",
-            "",
-            celestia_rpc_auth_param,
-            None,
-        ),
-        (
-            "rpc",
-            "kaanch",
-            161718,
-            "https://rpc.kaanch.network",
-            "kaanch_latestblocks",
-            "",
-            None,
-            None,
-        ),
-        (
-            "contract",
-            "linea",
-            59144,
-            &eth_rpc_url.as_str(),
-            "L2MerkleRootAdded",
-            "0xd19d4B5d358258f05D7B411E21A1460D11B0876F",
-            None,
-            Some("read_latest_l2_merkle_root_event"),
-        ),
-        (
-            "rpc",
-            "tron",
-            728126428,
-            "https://tron-evm-rpc.publicnode.com",
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-        (
-            "rpc",
-            "bsc",
-            56,
-            &bsc_rpc_url.as_str(),
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-        (
-            "contract",
-            "polygon_zkevm",
-            1101,
-            &eth_rpc_url.as_str(),
-            "VerifyBatchesTrustedAggregator",
-            "0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2",
-            None,
-            Some("read_latest_verify_batches_trusted_aggregator_event"),
-        ),
-        (
-            "rpc",
-            "arbitrum",
-            42161,
-            &arbi_rpc_url.as_str(),
-            "eth_getBlockByNumber",
-            "",
-            None,
-            None,
-        ),
-    ];
+fn build_sink(args: &Args, config: &Config) -> Result<Arc<dyn ProofSink>> {
+    Ok(match &args.sink {
+        SinkKind::Zmq => Arc::new(ZmqSink::new(config.sink.clone(), args.namespace.clone()).with_chains(sink_chains(args))),
+        SinkKind::Jsonl => Arc::new(JsonlSink),
+        SinkKind::Unix(path) => Arc::new(UnixSink::new(path.clone(), config.sink.clone())?),
+        SinkKind::Segment(dir) => Arc::new(SegmentSink::new(dir.clone(), config.segments.clone())?),
+    })
+}
 
-    loop {
-        // Calculate the next 10-minute interval (e.g., if it's 12:03, next is 12:10)
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let total_seconds = now.as_secs();
-        let total_minutes = total_seconds / 60;
-        
-        // Round up to the next interval (converting seconds to minutes)
-        let interval_minutes = proof_collection_interval / 60;
-        let next_interval = ((total_minutes / interval_minutes) + 1) * interval_minutes;
-        let next_target_seconds = next_interval * 60;
-        let sleep_seconds = next_target_seconds.saturating_sub(total_seconds);
-        
-        // Sleep until the next 10-minute interval
-        if sleep_seconds > 0 {
-            sleep(Duration::from_secs(sleep_seconds)).await;
-        }
-        
-        // Execute all tasks
-        for (_type, chain, chain_id, rpc_url, method, contract_address, auth, event_function) in block_fetch_params.clone() {
-            match _type {
-                "sdk" => {
-                    let fetched_number = read_block_number("avail");
-                    let block_number = if fetched_number.is_none() {
-                        String::from("")
-                    } else {
-                        format!("{}", &fetched_number.unwrap())
-                    };
-                    match br.fetch_block_hash(
-                        "avail".to_string(),
-                        chain_id,
-                        block_number.as_str(),
-                        last_block_hash,
-                    )
-                    .await
-                    {
-                        Ok((block_hash, block_number)) => {
-                            write_block_number("avail", block_number + 1)?;
-                            last_block_hash = Some(block_hash);
-                        }
-                        Err(e) => eprintln!("Failed to fetch block hash {:?}", e),
-                    }
-                }
-                "rpc" => {
-                    br.block_hash_from_rpc(chain, chain_id, rpc_url, method, auth).await?
-                }
-                 "contract" => {
-                     if let Some(func_name) = event_function {
-                        match func_name {
-                            "read_latest_l2_merkle_root_event" => {
-                                br.read_latest_l2_merkle_root_event(
-                                    rpc_url,
-                                    contract_address.parse::<Address>().unwrap(),
-                                    chain_id,
-                                    chain,
-                                ).await.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
-                            }
-                            "read_latest_verify_batches_trusted_aggregator_event" => {
-                                br.read_latest_verify_batches_trusted_aggregator_event(
-                                    rpc_url,
-                                    contract_address.parse::<Address>().unwrap(),
-                                    chain_id,
-                                    chain,
-                                ).await.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
-                            }
-                            _ => println!("unknown event function: {}", func_name),
-                        }
-                     } else {
-                        println!("unknown event function");
-                    }
-                 }
-                _ => {
-                    println!("unknown type call");
-                }
-            }
-            sleep(Duration::from_millis(1000)).await;
-        }
+/// The chains and settings of the loop, from the env, the config file and `args`
+fn loop_setup(args: &Args, config: &Config) -> Result<(Vec<ChainConfig>, LoopOptions)> {
+    let options = LoopOptions {
+        startup_delay: Duration::from_secs(args.startup_delay),
+        chain_timeout: args.chain_timeout.map(Duration::from_secs),
+        max_cycles: args.max_cycles,
+        heartbeat_file: args.heartbeat_file.clone(),
+        heartbeat_per_chain: args.heartbeat_per_chain,
+        ..LoopOptions::from_env()?
+    };
+    let chains = loop_chains(required_chain_id(args.avail_chain_id, "avail")?, config, options.interval)?;
+    if matches!(args.sink, SinkKind::Zmq) {
+        config
+            .sink
+            .check_zmq_endpoints(chains.iter().map(|chain| chain.name.as_str()))?;
     }
+    if args.no_address_allowlist {
+        eprintln!("Contract address allowlist check disabled by --no-address-allowlist");
+    } else {
+        config.check_contract_allowlist(&chains)?;
+    }
+    Ok((chains, options))
 }
 
-async fn rest_server(br: Arc<BlockReader>) -> Result<()> {
-    let mut router = Router::new();
+/// Chains whose proofs the selected mode sends to the sink
+fn sink_chains(args: &Args) -> Vec<String> {
+    let mut chains = Vec::new();
+    if matches!(args.mode, Mode::LOOP | Mode::BOTH) {
+        chains.extend(LOOP_CHAINS.iter().map(|chain| chain.to_string()));
+    }
+    if matches!(args.mode, Mode::REST | Mode::BOTH) {
+        chains.push(args.rest_chain_name.trim().to_string());
+    }
+    chains
+}
 
-    router.add_route(
-        "/add-block-by-number/".to_string(),
-        move |block_number: String| {
-            let br_clone = br.clone();
-            async move {
-                match br_clone.fetch_block_hash("o3".to_string(), 2000, &block_number, None).await { // O3 chain ID - update this to the correct value
-                    Ok((block_hash, _)) => {
-                        format!(
-                            "{{\"msg\": \"block hash added successfully\", \"block_hash\": \"0x{}\"}}",
-                            const_hex::encode(block_hash.as_bytes())
-                        )
-                    }
-                    Err(e) => format!(
-                        "{{\"error\": \"Failed to fetch block hash\", \"details\": \"{}\"}}",
-                        e.to_string()
-                    ),
-                }
-            }
-        },
-    );
+/// The proofs archived in the segments at `path` matching the filters, in the order
+/// they were archived, or the reason a record can't be replayed
+fn segment_proofs(
+    path: &std::path::Path,
+    chain: Option<&str>,
+    since: Option<u64>,
+) -> Result<Vec<std::result::Result<ProofEvent, String>>> {
+    let mut proofs = Vec::new();
+    for file in segment_files(path)? {
+        let records = read_segment(&file).map_err(|e| {
+            Error::new(e.kind(), format!("cannot read segment {}: {}", file.display(), e))
+        })?;
+        proofs.extend(
+            records
+                .into_iter()
+                .filter(|record| chain.is_none_or(|chain| record.chain == chain))
+                .filter(|record| since.is_none_or(|since| record.timestamp >= since))
+                .map(|record| {
+                    record
+                        .to_event()
+                        .map_err(|e| format!("record of {} in {}: {}", record.chain, file.display(), e))
+                }),
+        );
+    }
+    Ok(proofs)
+}
 
-    let router = Arc::new(router);
-    let listener = TcpListener::bind("0.0.0.0:8080").await?;
-    println!("server is listening on 8080");
-    loop {
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                let router_clone = Arc::clone(&router);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, router_clone).await {
-                        eprintln!("Connection handling error: {}", e);
-                    }
-                });
-            }
+/// The proofs of a sent-log matching the filters, oldest first, or the reason a record
+/// can't be replayed
+///
+/// The chain is `--chain` or else taken from the `<chain>-sent.log` file name. The
+/// sent-log doesn't keep the chain id, so the proofs are replayed one by one as sent.
+fn sent_log_proofs(
+    path: &std::path::Path,
+    chain: Option<&str>,
+    since: Option<u64>,
+) -> Result<Vec<std::result::Result<ProofEvent, String>>> {
+    let chain_name = chain
+        .map(String::from)
+        .or_else(|| {
+            path.file_name()?
+                .to_str()?
+                .strip_suffix("-sent.log")
+                .map(String::from)
+        })
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot tell the chain of {}, pass --chain", path.display()),
+            )
+        })?;
+    let records = read_sent_log(path).map_err(|e| {
+        Error::new(e.kind(), format!("cannot read sent-log {}: {}", path.display(), e))
+    })?;
+
+    Ok(records
+        .into_iter()
+        .filter(|record| since.is_none_or(|since| record.timestamp >= since))
+        .map(|record| {
+            let payload = record
+                .payload
+                .ok_or_else(|| format!("record #{} {} predates logged payloads", record.height, record.hash))?;
+            let hash = decode_block_hash(&record.hash)
+                .ok_or_else(|| format!("record #{} has an invalid hash {}", record.height, record.hash))?;
+            Ok(ProofEvent {
+                id: 0,
+                chain_name: chain_name.clone(),
+                chain_id: 0,
+                height: record.height,
+                hash: H256::from(hash),
+                abi_encoded_proof: payload,
+                label: format!("replay of #{} {}", record.height, record.hash),
+            })
+        })
+        .collect())
+}
+
+/// Sends previously forwarded proofs through `sink` one by one
+///
+/// Proofs go straight to the sink, bypassing the pending registry and the sent-log,
+/// so live state is left untouched. Records that can't be replayed count as failures,
+/// and the replay fails if there were any.
+async fn replay_proofs(
+    sink: Arc<dyn ProofSink>,
+    proofs: Vec<std::result::Result<ProofEvent, String>>,
+) -> Result<()> {
+    eprintln!("Replaying {} proofs", proofs.len());
+    let (mut replayed, mut failed) = (0u64, 0u64);
+    for proof in proofs {
+        let event = match proof {
+            Ok(event) => event,
             Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
+                eprintln!("Skipping {}", e);
+                failed += 1;
+                continue;
             }
+        };
+        let sink = sink.clone();
+        let acked = tokio::task::spawn_blocking(move || sink.deliver(&event))
+            .await
+            .unwrap_or(false);
+        if acked {
+            replayed += 1;
+        } else {
+            failed += 1;
         }
     }
+
+    eprintln!("Replayed {} proofs, {} failed", replayed, failed);
+    if failed > 0 {
+        return Err(Error::other(format!("{} proofs could not be replayed", failed)));
+    }
+    Ok(())
 }
 
-async fn handle_connection(mut stream: TcpStream, router: Arc<Router>) -> Result<()> {
-    let mut buffer = [0; 1024];
+/// Returns the chain id set with `--<chain>-chain-id`, there is no placeholder to fall
+/// back to
+fn required_chain_id(chain_id: Option<i32>, chain: &str) -> Result<i32> {
+    chain_id.ok_or_else(|| {
+        Error::other(format!(
+            "the {} chain id is not set, pass --{}-chain-id or set {}_CHAIN_ID",
+            chain,
+            chain,
+            chain.to_uppercase()
+        ))
+    })
+}
 
-    let bytes_read = stream.read(&mut buffer).await?;
-    if bytes_read == 0 {
-        return Err(Error::new(ErrorKind::UnexpectedEof, "Empty Request"));
+/// The chain `/add-block-by-number/` forwards for, named by `--rest-chain-name`
+///
+/// The name ends up in file names and in the `Location` of the route, so it is
+/// restricted to letters, digits, `-` and `_`.
+fn rest_chain(args: &Args) -> Result<ChainConfig> {
+    let name = args.rest_chain_name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "invalid --rest-chain-name '{}', use letters, digits, '-' and '_'",
+                args.rest_chain_name
+            ),
+        ));
     }
+    Ok(ChainConfig::sdk(name, required_chain_id(args.o3_chain_id, "o3")?))
+}
 
-    let request = String::from_utf8_lossy(&buffer[..]);
-    let path = request.split_whitespace().nth(1).unwrap_or("/");
+/// Calls an RPC endpoint once and prints the raw response and the fields extracted from it
+async fn probe_rpc(
+    rpc_url: &str,
+    methods: &[String],
+    chain_name: &str,
+    auth: Option<&str>,
+    headers: &[String],
+    hash_pointer: Option<&str>,
+    number_pointer: Option<&str>,
+) -> Result<()> {
+    let headers = headers
+        .iter()
+        .map(|header| {
+            header
+                .split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| Error::other(format!("Expected \"Name: value\", got {:?}", header)))
+        })
+        .collect::<Result<BTreeMap<String, String>>>()?;
+    let params = get_rpc_call_params(chain_name, Some("latest".to_string()));
 
-    let (status_code, content_type, body) = match router.handle(path).await {
-        Some(json_body) => ("200 OK", "application/json", json_body),
-        None => (
-            "404 NOT FOUND",
-            "text/html",
-            fs::read_to_string("src/404.html").unwrap(),
-        ),
+    let responses = if let [method] = methods {
+        println!("Calling {} {} with params {}", rpc_url, method, json!(params));
+        vec![rpc_call(rpc_url, method, params, auth, &headers, &RetryConfig::once())
+            .await
+            .map_err(|e| Error::other(format!("RPC call failed: {}", e)))?]
+    } else {
+        println!("Calling {} with a batch of {:?}, params {}", rpc_url, methods, json!(params));
+        let calls = methods.iter().map(|method| (method.clone(), params.clone())).collect();
+        rpc_call_batch(rpc_url, calls, auth, &headers, &RetryConfig::once())
+            .await
+            .map_err(|e| Error::other(format!("RPC batch call failed: {}", e)))?
     };
 
-    let response = format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
-        status_code,
-        content_type,
-        body.len(),
-        body
-    );
+    for (method, response) in methods.iter().zip(responses) {
+        if methods.len() > 1 {
+            println!("== {}", method);
+        }
+        println!(
+            "Raw response:\n{}",
+            serde_json::to_string_pretty(&response).unwrap_or_else(|_| response.to_string())
+        );
+
+        let extracted = if hash_pointer.is_some() || number_pointer.is_some() {
+            let read_pointer = |pointer: Option<&str>| {
+                pointer
+                    .and_then(|pointer| response.pointer(pointer))
+                    .map(|value| value.as_str().map(String::from).unwrap_or_else(|| value.to_string()))
+            };
+            Some((read_pointer(hash_pointer), read_pointer(number_pointer)))
+        } else {
+            read_rpc_response(response.clone(), chain_name)
+        };
 
-    stream.write_all(response.as_bytes()).await?;
-    stream.flush().await?;
+        match extracted {
+            Some((hash, number)) => {
+                println!("Extracted hash: {}", hash.as_deref().unwrap_or("<missing>"));
+                println!("Extracted number: {}", number.as_deref().unwrap_or("<missing>"));
+            }
+            None => println!("Parse error: response has no \"result\" field"),
+        }
+    }
 
     Ok(())
 }
+
@@ -1,11 +1,20 @@
 use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::{Read, Result, Write};
 use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{
+    block_number_op::{format_state_line, state_line_number, state_path},
+    util::retry_state_write,
+};
 use ethers::core::types::U64;
 
 pub fn read_last_merkle_root_block(chain_name: &str) -> Option<U64> {
     // File path
-    let file_path = format!("block_numbers/{}-merkle-block.txt", chain_name);
+    let file_path = state_path(&format!("{}-merkle-block.txt", chain_name));
 
     // Attempt to open the file
     let mut content = String::new();
@@ -16,7 +25,7 @@ pub fn read_last_merkle_root_block(chain_name: &str) -> Option<U64> {
                 eprintln!("Error reading merkle root block file '{}': {}", file_path, e);
                 return None; // Return a default value on error
             }
-            match content.trim().parse::<u64>() {
+            match state_line_number(&content).parse::<u64>() {
                 Ok(num) => Some(U64::from(num)),
                 Err(e) => {
                     eprintln!("Failed to parse merkle root block number from file '{}': {}", file_path, e);
@@ -34,44 +43,48 @@ pub fn read_last_merkle_root_block(chain_name: &str) -> Option<U64> {
     number
 }
 
-pub fn write_last_merkle_root_block(chain_name: &str, block_number: U64) -> Result<()> {
+/// Writes the last scanned block of a chain's event reader, `hash` is only kept by
+/// the rich `StateFormat`
+pub fn write_last_merkle_root_block(chain_name: &str, block_number: U64, hash: Option<&str>) -> Result<()> {
     // File path
-    let file_path = format!("block_numbers/{}-merkle-block.txt", chain_name);
+    let file_path = state_path(&format!("{}-merkle-block.txt", chain_name));
 
     // Ensure the directory exists
     if let Some(parent_dir) = Path::new(&file_path).parent() {
         create_dir_all(parent_dir)?;
     }
 
-    // Open the file for writing
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true) // Overwrite existing content
-        .open(&file_path)
-    {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!(
-                "Failed to open merkle root block file '{}'. Check if the path is correct and writable. Error: {}",
-                file_path, e
-            );
+    retry_state_write(&file_path, || {
+        // Open the file for writing
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true) // Overwrite existing content
+            .open(&file_path)
+        {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!(
+                    "Failed to open merkle root block file '{}'. Check if the path is correct and writable. Error: {}",
+                    file_path, e
+                );
+                return Err(e);
+            }
+        };
+
+        // Write the block number to the file
+        if let Err(e) = file.write_all(format_state_line(block_number, hash).as_bytes()) {
+            eprintln!("Failed to write to merkle root block file '{}'. Error: {}", file_path, e);
             return Err(e);
         }
-    };
-
-    // Write the block number to the file
-    if let Err(e) = file.write_all(format!("{}", block_number).as_bytes()) {
-        eprintln!("Failed to write to merkle root block file '{}'. Error: {}", file_path, e);
-        return Err(e);
-    }
 
-    Ok(())
+        Ok(())
+    })
 }
 
 pub fn read_last_merkle_root_hash(chain_name: &str) -> Option<String> {
     // File path
-    let file_path = format!("block_numbers/{}-merkle-hash.txt", chain_name);
+    let file_path = state_path(&format!("{}-merkle-hash.txt", chain_name));
 
     // Attempt to open the file
     let mut content = String::new();
@@ -94,35 +107,122 @@ pub fn read_last_merkle_root_hash(chain_name: &str) -> Option<String> {
 
 pub fn write_last_merkle_root_hash(chain_name: &str, merkle_root: &str) -> Result<()> {
     // File path
-    let file_path = format!("block_numbers/{}-merkle-hash.txt", chain_name);
+    let file_path = state_path(&format!("{}-merkle-hash.txt", chain_name));
 
     // Ensure the directory exists
     if let Some(parent_dir) = Path::new(&file_path).parent() {
         create_dir_all(parent_dir)?;
     }
 
-    // Open the file for writing
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true) // Overwrite existing content
-        .open(&file_path)
-    {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!(
-                "Failed to open merkle root hash file '{}'. Check if the path is correct and writable. Error: {}",
-                file_path, e
-            );
+    retry_state_write(&file_path, || {
+        // Open the file for writing
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true) // Overwrite existing content
+            .open(&file_path)
+        {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!(
+                    "Failed to open merkle root hash file '{}'. Check if the path is correct and writable. Error: {}",
+                    file_path, e
+                );
+                return Err(e);
+            }
+        };
+
+        // Write the merkle root hash to the file
+        if let Err(e) = file.write_all(merkle_root.as_bytes()) {
+            eprintln!("Failed to write to merkle root hash file '{}'. Error: {}", file_path, e);
             return Err(e);
         }
-    };
 
-    // Write the merkle root hash to the file
-    if let Err(e) = file.write_all(merkle_root.as_bytes()) {
-        eprintln!("Failed to write to merkle root hash file '{}'. Error: {}", file_path, e);
-        return Err(e);
+        Ok(())
+    })
+}
+
+/// A forwarded merkle root as kept in a chain's merkle-root history
+#[derive(Debug, Clone, Serialize)]
+pub struct MerkleRootRecord {
+    pub timestamp: u64,
+    /// Block of the event the root was read from
+    pub block: u64,
+    pub hash: String,
+}
+
+/// Roots kept per chain in its merkle-root history, from `MERKLE_HISTORY_SIZE` (default 100)
+pub fn merkle_history_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| {
+        std::env::var("MERKLE_HISTORY_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(100)
+    })
+}
+
+/// Appends a forwarded root to the history of `chain_name`, dropping the oldest roots
+/// beyond `merkle_history_size`
+pub fn push_merkle_root(chain_name: &str, block: U64, hash: &str) -> Result<()> {
+    let size = merkle_history_size();
+    if size == 0 {
+        return Ok(());
+    }
+
+    // File path
+    let file_path = state_path(&format!("{}-merkle-history.log", chain_name));
+
+    // Ensure the directory exists
+    if let Some(parent_dir) = Path::new(&file_path).parent() {
+        create_dir_all(parent_dir)?;
     }
 
-    Ok(())
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    let mut lines: Vec<String> = std::fs::read_to_string(&file_path)
+        .map(|content| content.lines().map(String::from).collect())
+        .unwrap_or_default();
+    // One tab separated record per line: timestamp, block, hash
+    lines.push(format!("{}\t{}\t{}", timestamp, block, hash));
+    let skip = lines.len().saturating_sub(size);
+    let content: String = lines[skip..].iter().map(|line| format!("{}\n", line)).collect();
+
+    retry_state_write(&file_path, || {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&file_path)?;
+        if let Err(e) = file.write_all(content.as_bytes()) {
+            eprintln!("Failed to write to merkle root history file '{}'. Error: {}", file_path, e);
+            return Err(e);
+        }
+        Ok(())
+    })
+}
+
+/// Returns the last `limit` roots of the history of `chain_name`, oldest first
+pub fn recent_merkle_roots(chain_name: &str, limit: usize) -> Vec<MerkleRootRecord> {
+    // File path
+    let file_path = state_path(&format!("{}-merkle-history.log", chain_name));
+
+    let Ok(content) = std::fs::read_to_string(&file_path) else {
+        return Vec::new();
+    };
+    let records: Vec<MerkleRootRecord> = content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(MerkleRootRecord {
+                timestamp: fields.next()?.parse().ok()?,
+                block: fields.next()?.parse().ok()?,
+                hash: fields.next()?.to_string(),
+            })
+        })
+        .collect();
+    let skip = records.len().saturating_sub(limit);
+    records.into_iter().skip(skip).collect()
 }
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+/// Process wide counters for the forwarding pipeline
+pub struct Metrics {
+    pub proofs_acked: AtomicU64,
+    pub proofs_unacked: AtomicU64,
+    pub zmq_send_errors: AtomicU64,
+    /// Sends given up on after all send retries
+    pub zmq_send_failures: AtomicU64,
+    pub zmq_recv_timeouts: AtomicU64,
+    pub zmq_recv_errors: AtomicU64,
+    /// Proofs re-sent from the sent-log after the aggregator reconnected
+    pub proofs_resent: AtomicU64,
+    /// Gauge of proofs sent to the aggregator and still waiting for their reply
+    pub proofs_in_flight: AtomicU64,
+    /// Gauge of chain operations running under the `--max-concurrency` cap
+    pub chain_operations_in_flight: AtomicU64,
+    /// Reorgs noticed on RPC chains, from a new head's parent hash
+    pub reorgs_detected: AtomicU64,
+    /// Blocks forwarded again because a reorg replaced the hash forwarded before
+    pub blocks_reforwarded: AtomicU64,
+    /// Requests currently in flight per provider group
+    provider_in_flight: Mutex<BTreeMap<String, u64>>,
+    /// Exponential moving average of the RPC round-trip time per chain in milliseconds
+    rpc_latency_ema_ms: Mutex<BTreeMap<String, f64>>,
+    /// Time from sending a proof to the aggregator's reply or timeout, per chain
+    zmq_reply_wait: Mutex<BTreeMap<String, WaitHistogram>>,
+}
+
+/// Upper bounds in milliseconds of the reply wait histogram buckets
+const REPLY_WAIT_BUCKETS_MS: [u64; 8] = [100, 250, 500, 1000, 2500, 5000, 10000, 20000];
+
+/// Cumulative histogram of waits, plus an EMA as the recent average
+#[derive(Default)]
+struct WaitHistogram {
+    /// Count per bucket of `REPLY_WAIT_BUCKETS_MS`, the last one counts longer waits
+    buckets: [u64; REPLY_WAIT_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: f64,
+    ema_ms: Option<f64>,
+}
+
+impl WaitHistogram {
+    fn to_json(&self) -> Value {
+        let mut buckets = serde_json::Map::new();
+        let mut cumulative = 0;
+        for (bound, count) in REPLY_WAIT_BUCKETS_MS.iter().zip(self.buckets) {
+            cumulative += count;
+            buckets.insert(format!("le_{}", bound), json!(cumulative));
+        }
+        buckets.insert("le_inf".to_string(), json!(self.count));
+        json!({
+            "buckets": buckets,
+            "count": self.count,
+            "sum_ms": self.sum_ms,
+        })
+    }
+}
+
+/// Weight of the newest sample in the latency EMA
+const LATENCY_EMA_ALPHA: f64 = 0.2;
+
+pub static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            proofs_acked: AtomicU64::new(0),
+            proofs_unacked: AtomicU64::new(0),
+            zmq_send_errors: AtomicU64::new(0),
+            zmq_send_failures: AtomicU64::new(0),
+            zmq_recv_timeouts: AtomicU64::new(0),
+            zmq_recv_errors: AtomicU64::new(0),
+            proofs_resent: AtomicU64::new(0),
+            proofs_in_flight: AtomicU64::new(0),
+            chain_operations_in_flight: AtomicU64::new(0),
+            reorgs_detected: AtomicU64::new(0),
+            blocks_reforwarded: AtomicU64::new(0),
+            provider_in_flight: Mutex::new(BTreeMap::new()),
+            rpc_latency_ema_ms: Mutex::new(BTreeMap::new()),
+            zmq_reply_wait: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decr(gauge: &AtomicU64) {
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn provider_entered(&self, group: &str) {
+        *self
+            .provider_in_flight
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn provider_left(&self, group: &str) {
+        if let Some(in_flight) = self.provider_in_flight.lock().unwrap().get_mut(group) {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Folds an RPC round-trip of `chain` into its latency EMA, the first sample seeds it
+    pub fn record_latency(&self, chain: &str, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1000.0;
+        self.rpc_latency_ema_ms
+            .lock()
+            .unwrap()
+            .entry(chain.to_string())
+            .and_modify(|ema| *ema += LATENCY_EMA_ALPHA * (sample - *ema))
+            .or_insert(sample);
+    }
+
+    /// Drops the latency EMA of `chain`, e.g. after it moved to another endpoint
+    pub fn reset_latency(&self, chain: &str) {
+        self.rpc_latency_ema_ms.lock().unwrap().remove(chain);
+    }
+
+    pub fn latency_ema_ms(&self, chain: &str) -> Option<f64> {
+        self.rpc_latency_ema_ms.lock().unwrap().get(chain).copied()
+    }
+
+    /// Chains with a latency EMA
+    pub fn latency_chains(&self) -> Vec<String> {
+        self.rpc_latency_ema_ms.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Adds the wait for an aggregator reply of `chain` to its histogram and recent average
+    pub fn record_reply_wait(&self, chain: &str, wait: Duration) {
+        let wait_ms = wait.as_secs_f64() * 1000.0;
+        let mut reply_wait = self.zmq_reply_wait.lock().unwrap();
+        let histogram = reply_wait.entry(chain.to_string()).or_default();
+        let bucket = REPLY_WAIT_BUCKETS_MS
+            .iter()
+            .position(|&bound| wait_ms <= bound as f64)
+            .unwrap_or(REPLY_WAIT_BUCKETS_MS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.count += 1;
+        histogram.sum_ms += wait_ms;
+        histogram.ema_ms = Some(match histogram.ema_ms {
+            Some(ema) => ema + LATENCY_EMA_ALPHA * (wait_ms - ema),
+            None => wait_ms,
+        });
+    }
+
+    /// Recent average wait for an aggregator reply of `chain`
+    pub fn reply_wait_ema_ms(&self, chain: &str) -> Option<f64> {
+        self.zmq_reply_wait.lock().unwrap().get(chain).and_then(|histogram| histogram.ema_ms)
+    }
+
+    /// Chains with a reply wait histogram
+    pub fn reply_wait_chains(&self) -> Vec<String> {
+        self.zmq_reply_wait.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let reply_wait: BTreeMap<String, Value> = self
+            .zmq_reply_wait
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(chain, histogram)| (chain.clone(), histogram.to_json()))
+            .collect();
+        json!({
+            "proofs_acked": self.proofs_acked.load(Ordering::Relaxed),
+            "proofs_unacked": self.proofs_unacked.load(Ordering::Relaxed),
+            "zmq_send_errors": self.zmq_send_errors.load(Ordering::Relaxed),
+            "zmq_send_failures": self.zmq_send_failures.load(Ordering::Relaxed),
+            "zmq_recv_timeouts": self.zmq_recv_timeouts.load(Ordering::Relaxed),
+            "zmq_recv_errors": self.zmq_recv_errors.load(Ordering::Relaxed),
+            "proofs_resent": self.proofs_resent.load(Ordering::Relaxed),
+            "proofs_in_flight": self.proofs_in_flight.load(Ordering::Relaxed),
+            "chain_operations_in_flight": self.chain_operations_in_flight.load(Ordering::Relaxed),
+            "reorgs_detected": self.reorgs_detected.load(Ordering::Relaxed),
+            "blocks_reforwarded": self.blocks_reforwarded.load(Ordering::Relaxed),
+            "provider_in_flight": *self.provider_in_flight.lock().unwrap(),
+            "rpc_latency_ema_ms": *self.rpc_latency_ema_ms.lock().unwrap(),
+            "zmq_reply_wait_ms": reply_wait,
+        })
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use avail_rust_client::ext::const_hex;
+use serde::Serialize;
+
+use crate::sink::ProofEvent;
+
+/// A proof between being queued for the sink and the end of its delivery
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingProof {
+    pub id: u64,
+    pub chain: String,
+    pub height: u128,
+    pub hash: String,
+    pub label: String,
+    pub queued_at: u64,
+    /// Handed to the sink, it can't be dropped anymore
+    pub in_flight: bool,
+}
+
+/// Why a pending proof could not be dropped
+pub enum DropError {
+    NotFound,
+    InFlight,
+}
+
+/// Registry of the proofs in the sink queue, listed and dropped through `/pending`
+///
+/// The queue itself is a channel that can't be inspected, so every queued proof is
+/// registered here as well. A dropped proof is skipped once the sink takes it off the
+/// channel.
+pub struct PendingProofs {
+    next_id: AtomicU64,
+    proofs: Mutex<BTreeMap<u64, PendingProof>>,
+}
+
+pub static PENDING: PendingProofs = PendingProofs::new();
+
+impl PendingProofs {
+    const fn new() -> Self {
+        PendingProofs {
+            next_id: AtomicU64::new(1),
+            proofs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Registers a proof about to be queued and returns its id
+    pub fn register(&self, event: &ProofEvent) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let proof = PendingProof {
+            id,
+            chain: event.chain_name.clone(),
+            height: event.height,
+            hash: format!("0x{}", const_hex::encode(event.hash.as_bytes())),
+            label: event.label.clone(),
+            queued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default(),
+            in_flight: false,
+        };
+        self.proofs.lock().unwrap().insert(id, proof);
+        id
+    }
+
+    /// Marks a proof as handed to the sink, false when it was dropped while queued
+    pub fn start_delivery(&self, id: u64) -> bool {
+        match self.proofs.lock().unwrap().get_mut(&id) {
+            Some(proof) => {
+                proof.in_flight = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets a proof once its delivery ended, acked or not
+    pub fn finish(&self, id: u64) {
+        self.proofs.lock().unwrap().remove(&id);
+    }
+
+    /// Pending proofs, oldest first
+    pub fn list(&self) -> Vec<PendingProof> {
+        self.proofs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Drops a proof that is still queued
+    pub fn drop_queued(&self, id: u64) -> Result<PendingProof, DropError> {
+        let mut proofs = self.proofs.lock().unwrap();
+        match proofs.get(&id) {
+            None => Err(DropError::NotFound),
+            Some(proof) if proof.in_flight => Err(DropError::InFlight),
+            Some(_) => Ok(proofs.remove(&id).expect("checked above")),
+        }
+    }
+}
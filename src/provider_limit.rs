@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+};
+
+use reqwest::Url;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    config::{ChainConfig, ProvidersConfig},
+    metrics::{Metrics, METRICS},
+};
+
+/// Caps concurrent requests to chains sharing a provider, e.g. Linea and Polygon zkEVM
+/// both reading through the Ethereum RPC, so they can't trip its rate limit together
+pub struct ProviderLimiter {
+    /// Chain name to provider group, overriding the RPC host
+    groups: HashMap<String, String>,
+    semaphores: HashMap<String, Arc<Semaphore>>,
+    /// Caps chain operations across all chains and provider groups, see `--max-concurrency`
+    operations: Arc<Semaphore>,
+}
+
+/// Held while a chain's requests to its provider group are in flight
+pub struct ProviderPermit {
+    group: String,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ProviderPermit {
+    fn drop(&mut self) {
+        METRICS.provider_left(&self.group);
+    }
+}
+
+/// Held while a chain operation runs, counted in `chain_operations_in_flight`
+pub struct OperationPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for OperationPermit {
+    fn drop(&mut self) {
+        METRICS.chain_operations_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ProviderLimiter {
+    pub fn new(config: &ProvidersConfig, max_concurrency: usize) -> Self {
+        ProviderLimiter {
+            operations: Arc::new(Semaphore::new(max_concurrency.max(1))),
+            groups: config.groups.clone(),
+            semaphores: config
+                .limits
+                .iter()
+                .map(|(group, limit)| (group.clone(), Arc::new(Semaphore::new((*limit).max(1)))))
+                .collect(),
+        }
+    }
+
+    /// The configured group of `chain`, otherwise the host of its RPC url
+    pub fn group_of(&self, chain: &ChainConfig) -> Option<String> {
+        self.groups.get(&chain.name).cloned().or_else(|| {
+            Url::parse(&chain.rpc_url)
+                .ok()?
+                .host_str()
+                .map(String::from)
+        })
+    }
+
+    /// Waits until fewer than `--max-concurrency` chain operations are running
+    ///
+    /// Taken before any provider permit, so the two are always acquired in the same order.
+    pub async fn acquire_operation(&self) -> OperationPermit {
+        let permit = self
+            .operations
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the operations semaphore is never closed");
+        Metrics::incr(&METRICS.chain_operations_in_flight);
+        OperationPermit { _permit: permit }
+    }
+
+    /// Waits for a free slot in the provider group of `chain`
+    ///
+    /// Groups without a configured limit are unlimited but still counted in the metrics,
+    /// chains without an RPC url (e.g. the Avail SDK) get no permit.
+    pub async fn acquire(&self, chain: &ChainConfig) -> Option<ProviderPermit> {
+        let group = self.group_of(chain)?;
+        let permit = match self.semaphores.get(&group) {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        METRICS.provider_entered(&group);
+        Some(ProviderPermit {
+            group,
+            _permit: permit,
+        })
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Instant};
+
+use crate::config::{BucketConfig, RateLimitConfig};
+
+/// Per-IP buckets kept before full, i.e. idle, ones are dropped
+const MAX_TRACKED_PEERS: usize = 10_000;
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(config: BucketConfig, now: Instant) -> Self {
+        TokenBucket {
+            tokens: config.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, config: BucketConfig, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.rate_per_sec).min(config.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&self, config: BucketConfig) -> bool {
+        self.tokens >= config.burst as f64
+    }
+}
+
+/// Token bucket limiter with an optional global and an optional per-IP bucket
+///
+/// A request is only admitted when every configured bucket has a token, so a request
+/// rejected by the global bucket doesn't use up its client's tokens.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    global: Mutex<Option<TokenBucket>>,
+    per_ip: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            global: Mutex::new(config.global.map(|bucket| TokenBucket::full(bucket, now))),
+            per_ip: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.global.is_some() || self.config.per_ip.is_some()
+    }
+
+    /// Takes a token for a request from `peer`, returns false when it must be rejected
+    pub fn try_acquire(&self, peer: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut global = self.global.lock().unwrap();
+        let mut per_ip = self.per_ip.lock().unwrap();
+
+        if let (Some(bucket), Some(config)) = (global.as_mut(), self.config.global) {
+            bucket.refill(config, now);
+            if bucket.tokens < 1.0 {
+                return false;
+            }
+        }
+        if let Some(config) = self.config.per_ip {
+            if per_ip.len() >= MAX_TRACKED_PEERS {
+                per_ip.retain(|_, bucket| {
+                    bucket.refill(config, now);
+                    !bucket.is_full(config)
+                });
+            }
+            let bucket = per_ip
+                .entry(peer)
+                .or_insert_with(|| TokenBucket::full(config, now));
+            bucket.refill(config, now);
+            if bucket.tokens < 1.0 {
+                return false;
+            }
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = global.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        true
+    }
+}
@@ -0,0 +1,581 @@
+//! The REST server: routes, request parsing and the `/ws` upgrade
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use avail_rust_client::{ext::const_hex, H256};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    time::Instant,
+};
+
+use crate::{
+    block_reader::BlockReader,
+    config::{ChainConfig, RateLimitConfig},
+    error::BlockReaderError,
+    merkle_root_op::{merkle_history_size, recent_merkle_roots},
+    metrics::METRICS,
+    pending::{DropError, PENDING},
+    rate_limit::RateLimiter,
+    router::{header_value, method_not_allowed, RequestContext, Response, Router},
+    sent_log::read_recent_sent_records,
+    sink::{ForwardedProof, ProofSink, SinkHealth},
+    websocket::{stream_forwards, websocket_key},
+};
+
+/// Limits on reading a request's line and headers
+const MAX_REQUEST_HEAD_BYTES: usize = 16 * 1024;
+const REQUEST_HEAD_TIMEOUT_SECS: u64 = 10;
+
+/// Default and maximum number of records returned by `/forward-history/`
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+const MAX_HISTORY_LIMIT: usize = 100;
+
+/// Serves the REST API on port 8080 until accepting connections fails for good
+///
+/// `/add-block-by-number/` forwards blocks of `rest_chain`, `sink` is probed by
+/// `/health` and `/ready`, and `/ws` streams what is published on `on_forward`.
+pub async fn rest_server(
+    br: Arc<BlockReader>,
+    rest_chain: ChainConfig,
+    add_block_rate_limit: RateLimitConfig,
+    sink: Arc<dyn ProofSink>,
+    on_forward: broadcast::Sender<ForwardedProof>,
+) -> Result<()> {
+    let mut router = Router::new();
+    router.set_prefix(&std::env::var("REST_PATH_PREFIX").unwrap_or_default());
+
+    let status_br = br.clone();
+    let health_br = br.clone();
+
+    // Clients that don't expect `201 Created` can opt back into a plain 200
+    let created_status = std::env::var("ADD_BLOCK_STATUS").map_or(true, |status| status != "200");
+    let prefix = router.prefix().to_string();
+    // A retried request for the block forwarded last reports `unchanged` instead of
+    // forwarding it again
+    let last_added_hash: Arc<Mutex<Option<H256>>> = Arc::new(Mutex::new(None));
+    router.add_route_with_context(
+        "/add-block-by-number/".to_string(),
+        move |block_number: String, context: RequestContext| {
+            let br_clone = br.clone();
+            let prefix = prefix.clone();
+            let last_added_hash = last_added_hash.clone();
+            let rest_chain = rest_chain.clone();
+            async move {
+                eprintln!("Block {:?} of {} requested by {}", block_number, rest_chain.name, context.peer);
+                let last_hash = *last_added_hash.lock().unwrap();
+                let _operation_permit = br_clone.acquire_operation().await;
+                match br_clone
+                    .fetch_block_hash(rest_chain.name.clone(), rest_chain.chain_id, &block_number, last_hash)
+                    .await
+                {
+                    Ok(fetched) if !fetched.forwarded => Response::ok(
+                        json!({
+                            "status": "unchanged",
+                            "block_hash": format!("0x{}", const_hex::encode(fetched.hash.as_bytes())),
+                        })
+                        .to_string(),
+                    ),
+                    Ok(fetched) => {
+                        *last_added_hash.lock().unwrap() = Some(fetched.hash);
+                        let response = Response::ok(
+                            json!({
+                                "status": "forwarded",
+                                "msg": "block hash added successfully",
+                                "block_hash": format!("0x{}", const_hex::encode(fetched.hash.as_bytes())),
+                            })
+                            .to_string(),
+                        );
+                        if created_status {
+                            Response {
+                                status: "201 CREATED",
+                                ..response
+                            }
+                            .with_header("Location", &format!("{}/forward-history/{}?height={}", prefix, rest_chain.name, fetched.height))
+                        } else {
+                            response
+                        }
+                    }
+                    Err(e) => {
+                        // The node failing is a bad gateway, a block it can't resolve a bad request
+                        let status = match e {
+                            BlockReaderError::InvalidData(_) => "400 BAD REQUEST",
+                            BlockReaderError::Rpc(_)
+                            | BlockReaderError::JsonRpc { .. }
+                            | BlockReaderError::Avail(_)
+                            | BlockReaderError::Provider(_) => "502 BAD GATEWAY",
+                            BlockReaderError::Abi(_) | BlockReaderError::State(_) => "500 INTERNAL SERVER ERROR",
+                        };
+                        Response {
+                            status,
+                            ..Response::ok(
+                                json!({ "error": "Failed to fetch block hash", "details": e.to_string() }).to_string(),
+                            )
+                        }
+                    }
+                }
+            }
+        },
+    );
+
+    router.set_rate_limit("/add-block-by-number/", RateLimiter::new(add_block_rate_limit));
+
+    router.add_route_with_context("/pending".to_string(), |param: String, context: RequestContext| async move {
+        if let Err(response) = authorize(&context) {
+            return response;
+        }
+        let param = param.split('?').next().unwrap_or_default().trim_matches('/');
+        if param.is_empty() {
+            if context.method != "GET" {
+                return method_not_allowed(&["GET"]);
+            }
+            return Response::ok(json!({ "pending": PENDING.list() }).to_string());
+        }
+        if context.method != "POST" {
+            return method_not_allowed(&["POST"]);
+        }
+        let id = match param.strip_suffix("/drop").map(str::parse::<u64>) {
+            Some(Ok(id)) => id,
+            _ => {
+                return Response {
+                    status: "400 BAD REQUEST",
+                    ..Response::ok(json!({ "error": "Expected /pending/{id}/drop" }).to_string())
+                }
+            }
+        };
+        match PENDING.drop_queued(id) {
+            Ok(dropped) => {
+                eprintln!(
+                    "Dropped pending {} of {} at {} ({}) on request of {}",
+                    dropped.label, dropped.chain, dropped.height, dropped.hash, context.peer
+                );
+                Response::ok(json!({ "dropped": dropped, "pending": PENDING.list() }).to_string())
+            }
+            Err(DropError::NotFound) => Response {
+                status: "404 NOT FOUND",
+                ..Response::ok(json!({ "error": "No pending proof with this id", "id": id }).to_string())
+            },
+            Err(DropError::InFlight) => Response {
+                status: "409 CONFLICT",
+                ..Response::ok(
+                    json!({ "error": "Proof is already being delivered", "id": id, "pending": PENDING.list() })
+                        .to_string(),
+                )
+            },
+        }
+    });
+    // Listing is a GET and dropping a POST, the handler tells them apart
+    router.set_methods("/pending", &["GET", "POST"]);
+
+    router.add_route(
+        "/forward-history/".to_string(),
+        |param: String| async move {
+            let (chain, limit) = parse_history_param(&param);
+            if chain.is_empty()
+                || !chain
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return json!({ "error": "Invalid chain name", "details": chain }).to_string();
+            }
+            let records = read_recent_sent_records(chain, limit);
+            json!({ "chain": chain, "limit": limit, "records": records }).to_string()
+        },
+    );
+
+    router.add_route(
+        "/merkle-history/".to_string(),
+        |param: String| async move {
+            let (chain, limit) = parse_history_param(&param);
+            if chain.is_empty()
+                || !chain
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return json!({ "error": "Invalid chain name", "details": chain }).to_string();
+            }
+            let limit = limit.min(merkle_history_size());
+            let roots = recent_merkle_roots(chain, limit);
+            json!({ "chain": chain, "limit": limit, "roots": roots }).to_string()
+        },
+    );
+
+    router.add_route("/status".to_string(), move |_| {
+        let br = status_br.clone();
+        async move {
+            Response::ok(json!({ "chains": br.status() }).to_string())
+                .with_header("Cache-Control", "no-store")
+        }
+    });
+
+    // The sink probe can be turned off where connecting to the aggregator is unwanted
+    let probe_sink_enabled =
+        std::env::var("HEALTH_SINK_PROBE").map_or(true, |value| value != "0" && value != "false");
+    let health_sink = sink.clone();
+    router.add_route("/health".to_string(), move |_| {
+        let br = health_br.clone();
+        let sink = health_sink.clone();
+        async move {
+            let stale_chains = br.stale_chains();
+            let sink_health = if probe_sink_enabled {
+                Some(probe_sink(sink).await)
+            } else {
+                None
+            };
+            let healthy = stale_chains.is_empty()
+                && sink_health.as_ref().is_none_or(|health| health.reachable);
+            health_response(
+                healthy,
+                json!({
+                    "chains": { "ok": stale_chains.is_empty(), "stale": stale_chains },
+                    "sink": sink_health,
+                }),
+            )
+        }
+    });
+
+    router.add_route("/ready".to_string(), move |_| {
+        let sink = sink.clone();
+        async move {
+            let sink_health = probe_sink(sink).await;
+            health_response(sink_health.reachable, json!({ "sink": sink_health }))
+        }
+    });
+
+    router.add_route("/metrics".to_string(), |_| async move {
+        Response::ok(METRICS.to_json().to_string()).with_header("Cache-Control", "no-store")
+    });
+
+    let router = Arc::new(router);
+    let listener = TcpListener::bind("0.0.0.0:8080").await?;
+    eprintln!("server is listening on 8080");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let router_clone = Arc::clone(&router);
+                let on_forward = on_forward.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, peer, router_clone, on_forward).await {
+                        eprintln!("Connection handling error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Error accepting connection: {}", e);
+            }
+        }
+    }
+}
+
+/// Checks the `Authorization: Bearer <API_TOKEN>` header of an operator route
+///
+/// Operator routes are disabled while no `API_TOKEN` is configured.
+fn authorize(context: &RequestContext) -> std::result::Result<(), Response> {
+    let Some(token) = std::env::var("API_TOKEN").ok().filter(|token| !token.is_empty()) else {
+        return Err(Response {
+            status: "403 FORBIDDEN",
+            ..Response::ok(json!({ "error": "API_TOKEN is not configured" }).to_string())
+        });
+    };
+    let presented = context
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    if presented != Some(token.as_str()) {
+        return Err(Response {
+            status: "401 UNAUTHORIZED",
+            ..Response::ok(json!({ "error": "Invalid or missing API token" }).to_string())
+        }
+        .with_header("WWW-Authenticate", "Bearer"));
+    }
+    Ok(())
+}
+
+/// Runs the blocking sink probe off the async workers
+async fn probe_sink(sink: Arc<dyn ProofSink>) -> SinkHealth {
+    tokio::task::spawn_blocking(move || sink.probe())
+        .await
+        .unwrap_or_else(|e| SinkHealth {
+            reachable: false,
+            ping_reply: None,
+            error: Some(format!("sink probe failed: {}", e)),
+        })
+}
+
+/// A `200 OK` or `503 SERVICE UNAVAILABLE` response with `status` added to `body`
+fn health_response(healthy: bool, mut body: serde_json::Value) -> Response {
+    body["status"] = json!(if healthy { "ok" } else { "unhealthy" });
+    Response {
+        status: if healthy { "200 OK" } else { "503 SERVICE UNAVAILABLE" },
+        ..Response::ok(body.to_string())
+    }
+    .with_header("Cache-Control", "no-store")
+}
+
+/// Splits a `chain?limit=N` route param, bounding the limit to `MAX_HISTORY_LIMIT`
+fn parse_history_param(param: &str) -> (&str, usize) {
+    let (chain, query) = param.split_once('?').unwrap_or((param, ""));
+    let limit = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "limit")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    (chain, limit)
+}
+
+/// Splits an HTTP request line into its method and path
+///
+/// Returns `None` unless the line is exactly `METHOD /path HTTP/x.y`.
+fn parse_request_line(request_line: &str) -> Option<(&str, &str)> {
+    let mut parts = request_line.split_whitespace();
+    let (method, path, version) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some()
+        || !method.chars().all(|c| c.is_ascii_uppercase())
+        || !path.starts_with('/')
+        || !version.starts_with("HTTP/")
+    {
+        return None;
+    }
+    Some((method, path))
+}
+
+/// Writes one JSON line per request to stderr, with the client address for audits
+fn log_request(peer: SocketAddr, request_line: &str, status: &str, started: Instant) {
+    let (method, path) = parse_request_line(request_line).unwrap_or(("", request_line));
+    let status = status.split_whitespace().next().unwrap_or_default();
+    eprintln!(
+        "{}",
+        json!({
+            "request": {
+                "peer": peer.to_string(),
+                "method": method,
+                "path": path,
+                "status": status.parse::<u16>().unwrap_or_default(),
+                "duration_ms": started.elapsed().as_millis() as u64,
+            }
+        })
+    );
+}
+
+/// Answers the single request of a connection through `router`, or upgrades a `/ws`
+/// request to a WebSocket streaming `on_forward`
+pub async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    router: Arc<Router>,
+    on_forward: broadcast::Sender<ForwardedProof>,
+) -> Result<()> {
+    let started = Instant::now();
+    let request = match read_request_head(&mut stream).await? {
+        Ok(head) => head,
+        Err(response) => {
+            log_request(peer, "", response.status, started);
+            return write_response(&mut stream, response).await;
+        }
+    };
+    let request_line = request.lines().next().unwrap_or_default();
+
+    if let Some((_, path)) = parse_request_line(request_line) {
+        if path == format!("{}/ws", router.prefix()) {
+            if let Some(key) = websocket_key(&request) {
+                log_request(peer, request_line, "101 SWITCHING PROTOCOLS", started);
+                return stream_forwards(stream, key, on_forward).await;
+            }
+        }
+    }
+
+    let response = match parse_request_line(request_line) {
+        None => Response {
+            status: "400 BAD REQUEST",
+            ..Response::ok(json!({ "error": "Malformed request line" }).to_string())
+        },
+        Some((method, path)) => match router
+            .handle(
+                path,
+                RequestContext {
+                    peer,
+                    method: method.to_string(),
+                    params: HashMap::new(),
+                    authorization: header_value(&request, "authorization").map(String::from),
+                },
+            )
+            .await
+        {
+            Some(response) => response,
+            None => Response {
+                status: "404 NOT FOUND",
+                content_type: "text/html",
+                ..Response::ok(
+                    fs::read_to_string("src/404.html").unwrap_or_else(|_| "Not Found".to_string()),
+                )
+            },
+        },
+    };
+
+    log_request(peer, request_line, response.status, started);
+    write_response(&mut stream, response).await
+}
+
+async fn write_response(stream: &mut TcpStream, response: Response) -> Result<()> {
+    let extra_headers: String = response
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        extra_headers,
+        response.body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Reads until the blank line ending the request line and headers, however many TCP
+/// segments they arrive in
+///
+/// Returns the error response to send when the head is cut short, too large or too
+/// slow to arrive. A connection closed before sending anything is an error.
+async fn read_request_head(stream: &mut TcpStream) -> Result<std::result::Result<String, Response>> {
+    let error = |status, error: &str| Response {
+        status,
+        ..Response::ok(json!({ "error": error }).to_string())
+    };
+
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    let deadline = Instant::now() + Duration::from_secs(REQUEST_HEAD_TIMEOUT_SECS);
+    loop {
+        let bytes_read = match tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await {
+            Ok(bytes_read) => bytes_read?,
+            Err(_) => return Ok(Err(error("408 REQUEST TIMEOUT", "Request head not received in time"))),
+        };
+        if bytes_read == 0 {
+            if head.is_empty() {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Empty Request"));
+            }
+            return Ok(Err(error("400 BAD REQUEST", "Incomplete request head")));
+        }
+        head.extend_from_slice(&buffer[..bytes_read]);
+
+        // Anything after the blank line is the body, which no route reads
+        let end = head
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|position| position + 4)
+            .or_else(|| head.windows(2).position(|window| window == b"\n\n").map(|position| position + 2));
+        if let Some(end) = end {
+            return Ok(Ok(String::from_utf8_lossy(&head[..end]).into_owned()));
+        }
+        if head.len() > MAX_REQUEST_HEAD_BYTES {
+            return Ok(Err(error(
+                "431 REQUEST HEADER FIELDS TOO LARGE",
+                "Request head too large",
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    /// Serves one connection with `handle_connection` behind a router answering
+    /// `GET /ping`, writes `chunks` to it one after another and returns the response
+    async fn exchange(chunks: &[&[u8]]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let mut router = Router::new();
+            router.add_route("/ping".to_string(), |_| async { Response::ok("pong".to_string()) });
+            let (on_forward, _) = broadcast::channel(1);
+            handle_connection(stream, peer, Arc::new(router), on_forward).await
+        });
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        for chunk in chunks {
+            client.write_all(chunk).await.unwrap();
+            client.flush().await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+        }
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap().unwrap();
+        response
+    }
+
+    #[test]
+    fn parses_request_line() {
+        assert_eq!(parse_request_line("GET /ping HTTP/1.1"), Some(("GET", "/ping")));
+        assert_eq!(parse_request_line(""), None);
+        assert_eq!(parse_request_line("GET"), None);
+        assert_eq!(parse_request_line("GET /ping"), None);
+        assert_eq!(parse_request_line("get /ping HTTP/1.1"), None);
+        assert_eq!(parse_request_line("GET ping HTTP/1.1"), None);
+        assert_eq!(parse_request_line("GET /ping HTTP/1.1 extra"), None);
+    }
+
+    #[tokio::test]
+    async fn answers_malformed_request_lines_with_bad_request() {
+        for request in [&b"\r\n\r\n"[..], b"GET\r\n\r\n", b"\x16\x03\x01\x02\x00 %%garbage%%\r\n\r\n"] {
+            let response = exchange(&[request]).await;
+            assert!(
+                response.starts_with("HTTP/1.1 400 BAD REQUEST"),
+                "{:?} got {}",
+                String::from_utf8_lossy(request),
+                response
+            );
+        }
+        assert!(exchange(&[b"GET /ping HTTP/1.1\r\n\r\n"]).await.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn reads_request_split_across_writes() {
+        // Split inside the path, and again inside the header terminator
+        let response = exchange(&[b"GET /pi", b"ng HTTP/1.1\r\nHost: localhost\r\n\r", b"\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("pong"), "{}", response);
+
+        // A malformed line is only judged once the whole head is in
+        let response = exchange(&[b"GET", b"\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn reads_large_request_heads_up_to_the_limit() {
+        // About 4KB of headers, several reads of the head buffer
+        let headers: String = (0..64).map(|index| format!("X-Padding-{:02}: {}\r\n", index, "a".repeat(48))).collect();
+        let request = format!("GET /ping HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers);
+        assert!(request.len() > 4096);
+        let response = exchange(&[request.as_bytes()]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("pong"), "{}", response);
+
+        // One byte over the limit without the blank line is refused, all of it is read
+        // before answering so the connection closes cleanly
+        let request = format!("GET /ping HTTP/1.1\r\nX-Padding: {}", "a".repeat(MAX_REQUEST_HEAD_BYTES));
+        let request = &request.as_bytes()[..MAX_REQUEST_HEAD_BYTES + 1];
+        let response = exchange(&[request]).await;
+        assert!(response.starts_with("HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE"), "{}", response);
+    }
+}
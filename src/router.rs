@@ -1,40 +1,288 @@
-use std::sync::Arc;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use serde_json::json;
+
+use crate::rate_limit::RateLimiter;
+
+type Handler = Arc<dyn Fn(&str, RequestContext) -> tokio::task::JoinHandle<Response> + Send + Sync>;
+
+/// What a handler may know about a request besides its route param
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub peer: SocketAddr,
+    /// Method of the request line, e.g. `GET`
+    pub method: String,
+    /// Named segments of the matched route, e.g. `number` of `/block/:number`
+    pub params: HashMap<String, String>,
+    /// Value of the `Authorization` header
+    pub authorization: Option<String>,
+}
+
+/// Returns the value of the first header called `name` in a raw request
+pub fn header_value<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// What a route handler answers with, plain `String` bodies convert into a 200 JSON response
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    /// Extra headers merged into the response after the default ones
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(body: String) -> Self {
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A `405 Method Not Allowed` response listing the `allowed` methods
+pub fn method_not_allowed(allowed: &[&str]) -> Response {
+    let allowed = allowed.join(", ");
+    Response {
+        status: "405 METHOD NOT ALLOWED",
+        ..Response::ok(json!({ "error": "Method not allowed", "allowed": allowed }).to_string())
+    }
+    .with_header("Allow", &allowed)
+}
+
+impl From<String> for Response {
+    fn from(body: String) -> Self {
+        Response::ok(body)
+    }
+}
+
+/// Matches `path` against a route pattern, returning the param handed to the handler
+/// along with the named segments
+///
+/// A plain pattern is a prefix and the param is the rest of the path after it. A
+/// pattern with `:name` segments, e.g. `/block/:number`, must match segment by segment,
+/// each named segment taking one non-empty segment, and the param is the query string
+/// if there is one.
+fn match_route(pattern: &str, path: &str) -> Option<(String, HashMap<String, String>)> {
+    if !pattern.split('/').any(|segment| segment.starts_with(':')) {
+        return path
+            .strip_prefix(pattern)
+            .map(|rest| (rest.to_string(), HashMap::new()));
+    }
+
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, format!("?{}", query)),
+        None => (path, String::new()),
+    };
+    let mut path_segments = path.split('/');
+    let mut params = HashMap::new();
+    for pattern_segment in pattern.split('/') {
+        let segment = path_segments.next()?;
+        match pattern_segment.strip_prefix(':') {
+            Some(name) if !segment.is_empty() => {
+                params.insert(name.to_string(), segment.to_string());
+            }
+            Some(_) => return None,
+            None if segment == pattern_segment => {}
+            None => return None,
+        }
+    }
+    if path_segments.next().is_some() {
+        return None;
+    }
+    Some((query, params))
+}
 
 #[derive(Clone)]
 pub struct Route {
     pattern: String,
-    handler: Arc<dyn Fn(&str) -> tokio::task::JoinHandle<String> + Send + Sync>,
+    handler: Handler,
+    /// Checked before the handler runs, rejected requests get a 429
+    limiter: Option<Arc<RateLimiter>>,
+    /// Methods the route accepts, others get a 405
+    methods: Vec<&'static str>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Router {
     routes: Vec<Route>,
+    prefix: String,
 }
 
 impl Router {
     pub fn new() -> Self {
-        Router { routes: Vec::new() }
+        Router {
+            routes: Vec::new(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Mounts every route under `prefix` (e.g. `/block-reader`), an empty prefix disables it
+    pub fn set_prefix(&mut self, prefix: &str) {
+        let prefix = prefix.trim_end_matches('/');
+        self.prefix = if prefix.is_empty() || prefix.starts_with('/') {
+            prefix.to_string()
+        } else {
+            format!("/{}", prefix)
+        };
     }
 
-    pub fn add_route<F, Fut>(&mut self, pattern: String, handler: F)
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn add_route<F, Fut, R>(&mut self, pattern: String, handler: F)
     where
         F: Fn(String) -> Fut + 'static + Send + Sync,
-        Fut: std::future::Future<Output = String> + Send + 'static,
+        Fut: std::future::Future<Output = R> + Send + 'static,
+        R: Into<Response> + 'static,
     {
+        self.add_route_with_context(pattern, move |param, _| handler(param));
+    }
+
+    /// Like `add_route`, the handler also gets the request's `RequestContext`
+    pub fn add_route_with_context<F, Fut, R>(&mut self, pattern: String, handler: F)
+    where
+        F: Fn(String, RequestContext) -> Fut + 'static + Send + Sync,
+        Fut: std::future::Future<Output = R> + Send + 'static,
+        R: Into<Response> + 'static,
+    {
+        let handler = Arc::new(handler);
         self.routes.push(Route {
             pattern,
-            handler: Arc::new(move |id| tokio::spawn(handler(id.to_string()))),
+            handler: Arc::new(move |id, context| {
+                let future = handler(id.to_string(), context);
+                tokio::spawn(async move { future.await.into() })
+            }),
+            limiter: None,
+            methods: vec!["GET"],
         });
     }
 
-    pub async fn handle(&self, path: &str) -> Option<String> {
+    /// Sets the methods the route registered under `pattern` accepts, `GET` by default
+    pub fn set_methods(&mut self, pattern: &str, methods: &[&'static str]) {
+        for route in self.routes.iter_mut().filter(|route| route.pattern == pattern) {
+            route.methods = methods.to_vec();
+        }
+    }
+
+    /// Rate limits the route registered under `pattern`, a disabled limiter is ignored
+    pub fn set_rate_limit(&mut self, pattern: &str, limiter: RateLimiter) {
+        if !limiter.is_enabled() {
+            return;
+        }
+        let limiter = Arc::new(limiter);
+        for route in self.routes.iter_mut().filter(|route| route.pattern == pattern) {
+            route.limiter = Some(limiter.clone());
+        }
+    }
+
+    /// Routes a request to the first route matching its path, per-IP rate limits key on
+    /// the IP of its peer
+    ///
+    /// A method the matching route doesn't accept gets a 405 before the rate limit is
+    /// checked.
+    pub async fn handle(&self, path: &str, mut context: RequestContext) -> Option<Response> {
+        // Requests outside of the mount point are not found
+        let path = path.strip_prefix(self.prefix.as_str())?;
         for route in &self.routes {
-            if path.starts_with(&route.pattern) {
-                let param = path.replace(&route.pattern, "");
-                let handle = (route.handler)(&param);
+            if let Some((param, params)) = match_route(&route.pattern, path) {
+                if !route.methods.contains(&context.method.as_str()) {
+                    return Some(method_not_allowed(&route.methods));
+                }
+                if let Some(limiter) = &route.limiter {
+                    if !limiter.try_acquire(context.peer.ip()) {
+                        return Some(Response {
+                            status: "429 TOO MANY REQUESTS",
+                            ..Response::ok(json!({ "error": "Rate limit exceeded" }).to_string())
+                        }
+                        .with_header("Retry-After", "1"));
+                    }
+                }
+                context.params = params;
+                let handle = (route.handler)(&param, context);
                 return Some(handle.await.unwrap());
             }
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn plain_pattern_hands_the_rest_of_the_path() {
+        assert_eq!(match_route("/merkle-history/", "/merkle-history/linea"), Some(("linea".to_string(), params(&[]))));
+        // A param repeating the pattern text is kept whole
+        assert_eq!(
+            match_route("/merkle-history/", "/merkle-history/merkle-history"),
+            Some(("merkle-history".to_string(), params(&[])))
+        );
+        assert_eq!(
+            match_route("/merkle-history/", "/merkle-history//merkle-history/"),
+            Some(("/merkle-history/".to_string(), params(&[])))
+        );
+        assert_eq!(match_route("/merkle-history/", "/forward-history/merkle-history/"), None);
+    }
+
+    #[test]
+    fn named_segments_match_one_segment_each() {
+        assert_eq!(match_route("/block/:number", "/block/42"), Some((String::new(), params(&[("number", "42")]))));
+        assert_eq!(
+            match_route("/block/:number", "/block/42?finalized=true"),
+            Some(("?finalized=true".to_string(), params(&[("number", "42")])))
+        );
+        assert_eq!(
+            match_route("/chain/:name/block/:number", "/chain/linea/block/7"),
+            Some((String::new(), params(&[("name", "linea"), ("number", "7")])))
+        );
+        // A named segment may repeat the pattern's own text
+        assert_eq!(match_route("/block/:number", "/block/block"), Some((String::new(), params(&[("number", "block")]))));
+        assert_eq!(match_route("/block/:number", "/block/"), None);
+        assert_eq!(match_route("/block/:number", "/block"), None);
+        assert_eq!(match_route("/block/:number", "/block/42/extra"), None);
+        assert_eq!(match_route("/block/:number", "/blocks/42"), None);
+    }
+
+    #[tokio::test]
+    async fn handle_routes_named_segments_and_checks_the_method() {
+        let mut router = Router::new();
+        router.add_route_with_context("/block/:number".to_string(), |_, context: RequestContext| async move {
+            Response::ok(context.params["number"].clone())
+        });
+        let context = |method: &str| RequestContext {
+            peer: "127.0.0.1:1".parse().unwrap(),
+            method: method.to_string(),
+            params: HashMap::new(),
+            authorization: None,
+        };
+
+        let response = router.handle("/block/42", context("GET")).await.unwrap();
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "42");
+        let response = router.handle("/block/42", context("POST")).await.unwrap();
+        assert_eq!(response.status, "405 METHOD NOT ALLOWED");
+        assert!(router.handle("/block/", context("GET")).await.is_none());
+    }
+}
@@ -1,15 +1,162 @@
 pub mod rpc {
+    use ethers::providers::{Ipc, Middleware, Provider};
     use reqwest::Client;
     use serde_json::json;
     use serde_json::Value;
+    use std::collections::BTreeMap;
     use std::error::Error;
+    use std::fmt;
+    use std::path::Path;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    use crate::config::RetryConfig;
+
+    /// Characters of a non-JSON body kept in errors and logs
+    const MAX_BODY_PREVIEW_CHARS: usize = 200;
+
+    /// The endpoint answered with a body that isn't JSON, e.g. a gateway's HTML error page
+    #[derive(Debug)]
+    pub struct NonJsonResponse {
+        pub status: reqwest::StatusCode,
+        /// Start of the body with whitespace collapsed, at most `MAX_BODY_PREVIEW_CHARS`
+        pub preview: String,
+    }
+
+    impl fmt::Display for NonJsonResponse {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "non-JSON response with HTTP status {}: {}", self.status, self.preview)
+        }
+    }
+
+    impl Error for NonJsonResponse {}
+
+    /// The endpoint answered with a JSON-RPC error object instead of a result
+    #[derive(Debug)]
+    pub struct JsonRpcError {
+        pub code: i64,
+        pub message: String,
+        pub data: Option<Value>,
+    }
+
+    impl fmt::Display for JsonRpcError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "JSON-RPC error {}: {}", self.code, self.message)?;
+            if let Some(data) = &self.data {
+                write!(f, " ({})", data)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Error for JsonRpcError {}
+
+    impl JsonRpcError {
+        /// The error of a response carrying `"error"` but no `"result"`
+        fn from_response(response: &Value) -> Option<Self> {
+            if response.get("result").is_some() {
+                return None;
+            }
+            let error = response.get("error")?;
+            Some(JsonRpcError {
+                code: error.get("code").and_then(Value::as_i64).unwrap_or_default(),
+                message: error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+                    .unwrap_or_else(|| error.to_string()),
+                data: error.get("data").cloned(),
+            })
+        }
+    }
+
+    /// The endpoint didn't answer within `RPC_TIMEOUT_SECS` or the call's `RetryConfig::timeout_ms`
+    #[derive(Debug)]
+    pub struct RpcTimeout {
+        pub url: String,
+        pub timeout: Duration,
+    }
+
+    impl fmt::Display for RpcTimeout {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "RPC {} timed out after {:?}", self.url, self.timeout)
+        }
+    }
+
+    impl Error for RpcTimeout {}
+
+    /// Timeout of a whole HTTP RPC request, from `RPC_TIMEOUT_SECS` (default 15)
+    fn rpc_timeout() -> Duration {
+        static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+        *TIMEOUT.get_or_init(|| {
+            Duration::from_secs(
+                std::env::var("RPC_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|secs| secs.parse().ok())
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(15),
+            )
+        })
+    }
+
+    /// The HTTP client shared by all RPC calls, so pooled keep-alive connections and
+    /// their TLS sessions are reused across chains, loop iterations and REST requests
+    fn http_client() -> Result<&'static Client, reqwest::Error> {
+        static CLIENT: OnceLock<Client> = OnceLock::new();
+        if let Some(client) = CLIENT.get() {
+            return Ok(client);
+        }
+        let client = Client::builder().timeout(rpc_timeout()).build()?;
+        Ok(CLIENT.get_or_init(|| client))
+    }
+
+    /// Turns a timed out request into an `RpcTimeout` naming the endpoint
+    fn request_error(rpc_url: &str, error: reqwest::Error, timeout: Duration) -> Box<dyn Error> {
+        if error.is_timeout() {
+            RpcTimeout {
+                url: rpc_url.to_string(),
+                timeout,
+            }
+            .into()
+        } else {
+            error.into()
+        }
+    }
+
+    fn body_preview(body: &str) -> String {
+        let collapsed = body.split_whitespace().collect::<Vec<_>>().join(" ");
+        match collapsed.char_indices().nth(MAX_BODY_PREVIEW_CHARS) {
+            Some((end, _)) => format!("{}...", &collapsed[..end]),
+            None => collapsed,
+        }
+    }
+
+    /// Returns the socket path when `rpc_url` points at a local IPC endpoint,
+    /// either as `ipc:///path/geth.ipc` or a plain filesystem path
+    fn ipc_path(rpc_url: &str) -> Option<&str> {
+        rpc_url
+            .strip_prefix("ipc://")
+            .or_else(|| rpc_url.starts_with('/').then_some(rpc_url))
+    }
+
+    /// Makes a JSON-RPC call, over IPC for local socket paths and HTTP otherwise
+    ///
+    /// HTTP calls failing to connect, timing out or answered with a 5xx status are
+    /// retried per `retry`, returning the last error once attempts run out. A response
+    /// carrying a JSON-RPC `"error"` instead of a `"result"` is never retried and
+    /// returned as a `JsonRpcError`.
     pub async fn rpc_call(
         rpc_url: &str,
         method: &str,
         params: Vec<Value>,
         auth: Option<&str>,
+        headers: &BTreeMap<String, String>,
+        retry: &RetryConfig,
     ) -> Result<Value, Box<dyn Error>> {
-        let client = Client::new();
+        if let Some(path) = ipc_path(rpc_url) {
+            return ipc_call(path, method, params).await;
+        }
 
         let request_body = json!({
             "jsonrpc": "2.0",
@@ -17,22 +164,215 @@ pub mod rpc {
             "params": params,
             "id": 1
         });
+        let response = post_json(rpc_url, method, &request_body, auth, headers, retry).await?;
+        match JsonRpcError::from_response(&response) {
+            Some(error) => {
+                eprintln!("{} {} returned a {}", rpc_url, method, error);
+                Err(error.into())
+            }
+            None => Ok(response),
+        }
+    }
 
-        let mut request = client
-            .post(rpc_url)
-            .header("Content-Type", "application/json");
-        
-        if let Some(auth_value) = auth {
-            request = request.header("Authorization", auth_value);
+    /// Makes several calls in one JSON-RPC batch request
+    ///
+    /// Responses are matched to the calls by `id` and returned in the order of `calls`,
+    /// however the server orders them. A server rejecting the batch as a whole answers
+    /// with a single object instead of an array, which is returned as the error. Over IPC
+    /// the calls are made one after another.
+    pub async fn rpc_call_batch(
+        rpc_url: &str,
+        calls: Vec<(String, Vec<Value>)>,
+        auth: Option<&str>,
+        headers: &BTreeMap<String, String>,
+        retry: &RetryConfig,
+    ) -> Result<Vec<Value>, Box<dyn Error>> {
+        if let Some(path) = ipc_path(rpc_url) {
+            let mut responses = Vec::with_capacity(calls.len());
+            for (method, params) in calls {
+                responses.push(ipc_call(path, &method, params).await?);
+            }
+            return Ok(responses);
         }
-        
-        let response = request
-            .json(&request_body)
-            .send()
-            .await?;
 
-        let response_body: Value = response.json().await?;
+        let request_body: Vec<Value> = calls
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params,
+                    "id": id
+                })
+            })
+            .collect();
+        let label = format!("batch of {}", calls.len());
+        let response = post_json(rpc_url, &label, &json!(request_body), auth, headers, retry).await?;
+
+        let Value::Array(mut responses) = response else {
+            return Err(format!("{} rejected the {}: {}", rpc_url, label, response).into());
+        };
+        (0..calls.len())
+            .map(|id| {
+                let position = responses
+                    .iter()
+                    .position(|response| response.get("id").and_then(Value::as_u64) == Some(id as u64))
+                    .ok_or_else(|| format!("no response to {} (id {}) in the batch", calls[id].0, id))?;
+                Ok(responses.swap_remove(position))
+            })
+            .collect()
+    }
+
+    /// POSTs a JSON-RPC body, retrying transient failures per `retry`
+    async fn post_json(
+        rpc_url: &str,
+        method: &str,
+        request_body: &Value,
+        auth: Option<&str>,
+        headers: &BTreeMap<String, String>,
+        retry: &RetryConfig,
+    ) -> Result<Value, Box<dyn Error>> {
+        let client = http_client()?;
+
+        let max_attempts = retry.max_attempts.max(1);
+        let timeout = retry.timeout_ms.map(Duration::from_millis).unwrap_or_else(rpc_timeout);
+        let mut attempt = 1;
+        loop {
+            let mut request = client
+                .post(rpc_url)
+                .timeout(timeout)
+                .header("Content-Type", "application/json");
+
+            if let Some(auth_value) = auth.filter(|value| !value.trim().is_empty()) {
+                request = request.header("Authorization", auth_value);
+            }
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+
+            let failure = match request.json(request_body).send().await {
+                Ok(response) if response.status().is_server_error() && attempt < max_attempts => {
+                    format!("HTTP status {}", response.status())
+                }
+                Ok(response) => return read_response(rpc_url, method, response, timeout).await,
+                Err(e) if is_transient(&e) && attempt < max_attempts => {
+                    request_error(rpc_url, e, timeout).to_string()
+                }
+                Err(e) => return Err(request_error(rpc_url, e, timeout)),
+            };
+
+            let delay = retry.backoff(attempt - 1);
+            eprintln!(
+                "{} {} failed: {}, retrying in {:?} (attempt {}/{})",
+                rpc_url, method, failure, delay, attempt + 1, max_attempts
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Connection failures and timeouts, which a later attempt may not hit
+    fn is_transient(error: &reqwest::Error) -> bool {
+        error.is_connect() || error.is_timeout() || error.is_request()
+    }
 
-        Ok(response_body)
+    async fn read_response(
+        rpc_url: &str,
+        method: &str,
+        response: reqwest::Response,
+        timeout: Duration,
+    ) -> Result<Value, Box<dyn Error>> {
+        // Read the raw body first so a non-JSON answer can be shown instead of a serde error
+        let status = response.status();
+        let body = response.text().await.map_err(|e| request_error(rpc_url, e, timeout))?;
+        match serde_json::from_str::<Value>(&body) {
+            Ok(response_body) => Ok(response_body),
+            Err(_) => {
+                let error = NonJsonResponse {
+                    status,
+                    preview: body_preview(&body),
+                };
+                eprintln!("{} {} returned a {}", rpc_url, method, error);
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Makes the call over a node's IPC socket
+    ///
+    /// The result is wrapped in a JSON-RPC envelope so callers parse it exactly
+    /// like an HTTP response.
+    async fn ipc_call(path: &str, method: &str, params: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Err(format!("IPC socket '{}' does not exist, is the node running?", path).into());
+        }
+
+        let provider = Provider::<Ipc>::connect_ipc(path).await?;
+        let result: Value = provider.provider().request(method, params).await?;
+
+        Ok(json!({
+            "jsonrpc": "2.0",
+            "result": result,
+            "id": 1
+        }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        /// Answers one JSON-RPC request on `listener` with an empty result and returns
+        /// the request's `Authorization` header
+        async fn serve_one(listener: TcpListener) -> Option<String> {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let head_end = loop {
+                if let Some(position) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break position + 4;
+                }
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            };
+            let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+            let header = |name: &str| {
+                head.lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.trim().to_string())
+            };
+            let content_length: usize = header("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+            while request.len() < head_end + content_length {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            let body = json!({ "jsonrpc": "2.0", "id": 1, "result": {} }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            header("authorization")
+        }
+
+        #[tokio::test]
+        async fn sends_authorization_header_only_when_set() {
+            for (auth, sent) in [(Some("Bearer secret"), Some("Bearer secret")), (Some("  "), None), (None, None)] {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let url = format!("http://{}", listener.local_addr().unwrap());
+                let server = tokio::spawn(serve_one(listener));
+                rpc_call(&url, "header.NetworkHead", vec![], auth, &BTreeMap::new(), &RetryConfig::once())
+                    .await
+                    .unwrap();
+                assert_eq!(server.await.unwrap().as_deref(), sent, "auth {:?}", auth);
+            }
+        }
     }
 }
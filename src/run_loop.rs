@@ -0,0 +1,476 @@
+//! The collection loop: every interval each configured chain is read once and its
+//! proofs are queued for the sink
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use avail_rust_client::{ext::const_hex, H256};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::{
+    block_number_op::{read_block_number, write_block_number},
+    block_reader::BlockReader,
+    config::{bearer_auth, ChainConfig, ChainKind, Config},
+    error::BlockReaderError,
+    events::EventSpec,
+    util::{debug_enabled, duration_until_next_boundary, touch_heartbeat, write_state_blocking},
+};
+
+/// Names of the chains `loop_chains` returns, before they are sorted by offset
+pub const LOOP_CHAINS: [&str; 11] = [
+    "avail",
+    "onlylayer",
+    "mintchain",
+    "u2u",
+    "celestia",
+    "kaanch",
+    "linea",
+    "tron",
+    "bsc",
+    "polygon_zkevm",
+    "arbitrum",
+];
+
+/// Settings of `BlockReader::run_loop`
+#[derive(Debug, Clone)]
+pub struct LoopOptions {
+    /// Cycles start on multiples of it on the wall clock, `PROOF_COLLECTION_INTERVAL`
+    pub interval: Duration,
+    /// How much earlier than one interval after the last cycle the next may start after
+    /// a wall clock step, `MAX_CLOCK_SKEW_SECS`
+    pub max_clock_skew: Duration,
+    /// Waited before the first cycle
+    pub startup_delay: Duration,
+    /// How long a chain's processing in a cycle may take before it is abandoned until
+    /// the next cycle, `None` never gives up on a chain
+    pub chain_timeout: Option<Duration>,
+    /// Stops after this many cycles, `None` runs forever
+    pub max_cycles: Option<u64>,
+    /// Touched after every cycle without errors
+    pub heartbeat_file: Option<String>,
+    /// Also touch `<heartbeat_file>.<chain>` after each chain processed successfully
+    pub heartbeat_per_chain: bool,
+}
+
+impl LoopOptions {
+    /// Reads the interval and clock skew from `PROOF_COLLECTION_INTERVAL` (default 600s)
+    /// and `MAX_CLOCK_SKEW_SECS` (default 60s), everything else is off
+    pub fn from_env() -> Result<Self> {
+        Ok(LoopOptions {
+            interval: Duration::from_secs(env_u64("PROOF_COLLECTION_INTERVAL", 600)?.max(1)),
+            max_clock_skew: Duration::from_secs(env_u64("MAX_CLOCK_SKEW_SECS", 60)?),
+            startup_delay: Duration::ZERO,
+            chain_timeout: None,
+            max_cycles: None,
+            heartbeat_file: None,
+            heartbeat_per_chain: false,
+        })
+    }
+}
+
+/// The chains the loop reads, configured from the env and `config`, in the order they
+/// run in a cycle
+///
+/// `interval` is only used to warn about a `CHAIN_OFFSETS` offset that delays the next
+/// cycle.
+pub fn loop_chains(avail_chain_id: i32, config: &Config, interval: Duration) -> Result<Vec<ChainConfig>> {
+    // An empty token would send a bare `Bearer ` header, which some nodes reject
+    let celestia_auth = std::env::var("CELESTIA_RPC_AUTH")
+        .ok()
+        .and_then(|token| bearer_auth(&token));
+    if celestia_auth.is_some() {
+        eprintln!("Celestia RPC auth configured");
+    } else {
+        eprintln!("Celestia RPC auth not configured, requests are sent without an Authorization header");
+    }
+    let celestia_rpc_url = std::env::var("CELESTIA_RPC_URL").unwrap_or_else(|_| "http://localhost:26658".to_string());
+    let eth_rpc_url = std::env::var("ETH_RPC_URL").unwrap_or_else(|_| "https://0xrpc.io/eth".to_string());
+    let bsc_rpc_url = std::env::var("BSC_RPC_URL").unwrap_or_else(|_| "https://bsc-rpc.publicnode.com".to_string());
+    let arbi_rpc_urls = rpc_urls_from_env("ARBI_RPC_URLS", "ARBI_RPC_URL", "https://arb1.arbitrum.io/rpc");
+    // The rollup manager emits events for every rollup, unset forwards all of them
+    let polygon_zkevm_rollup_id = std::env::var("POLYGON_ZKEVM_ROLLUP_ID")
+        .ok()
+        .map(|id| {
+            id.trim().parse::<u32>().map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, "POLYGON_ZKEVM_ROLLUP_ID must be a uint32")
+            })
+        })
+        .transpose()?;
+    // Comma separated chains whose blocks carry EIP-4844 blob gas fields worth forwarding
+    let blob_field_chains = std::env::var("BLOB_FIELD_CHAINS").unwrap_or_default();
+    // Comma separated contract chains whose logs are checked against their receipt before forwarding
+    let verify_receipt_chains = std::env::var("VERIFY_RECEIPT_CHAINS").unwrap_or_default();
+    // Comma separated `chain=depth` pairs, contract events are only read that many blocks deep
+    let contract_confirmations = std::env::var("CONTRACT_CONFIRMATIONS").unwrap_or_default();
+    // Comma separated `chain=depth` pairs, RPC chains forward the block that many below the head
+    let rpc_confirmations = std::env::var("RPC_CONFIRMATIONS").unwrap_or_default();
+    // Comma separated `chain=url` pairs, RPC chains following `newHeads` over that WebSocket
+    let ws_rpc_urls = std::env::var("WS_RPC_URLS").unwrap_or_default();
+    // Comma separated `chain=seconds` pairs, each chain is read that long after the cycle starts
+    let chain_offsets = std::env::var("CHAIN_OFFSETS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, secs)| {
+            let secs = secs.trim().parse::<u64>().map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, "CHAIN_OFFSETS offsets must be unsigned integers")
+            })?;
+            Ok((name.trim().to_string(), Duration::from_secs(secs)))
+        })
+        .collect::<Result<HashMap<String, Duration>>>()?;
+    let mut block_fetch_params: Vec<ChainConfig> = vec![
+        ChainConfig::sdk("avail", avail_chain_id),
+        ChainConfig::rpc("onlylayer", 5820948, "https://onlylayer.org", "eth_getBlockByNumber"),
+        ChainConfig::rpc("mintchain", 185, "https://global.rpc.mintchain.io", "eth_getBlockByNumber"),
+        ChainConfig::rpc("u2u", 39, "https://rpc-mainnet.u2u.xyz", "eth_getBlockByNumber"),
+        ChainConfig::rpc("celestia", 131415, &celestia_rpc_url, "header.NetworkHead")
+            .with_auth(celestia_auth.as_deref())
+            .with_expected_block_time(6),
+        ChainConfig::rpc("kaanch", 161718, "https://rpc.kaanch.network", "kaanch_latestblocks"),
+        ChainConfig::contract("linea", 59144, &eth_rpc_url, "0xd19d4B5d358258f05D7B411E21A1460D11B0876F")
+            .with_event(EventSpec::l2_merkle_root_added()),
+        ChainConfig::rpc("tron", 728126428, "https://tron-evm-rpc.publicnode.com", "eth_getBlockByNumber")
+            .with_expected_block_time(3),
+        ChainConfig::rpc("bsc", 56, &bsc_rpc_url, "eth_getBlockByNumber").with_expected_block_time(3),
+        ChainConfig::contract("polygon_zkevm", 1101, &eth_rpc_url, "0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2")
+            .with_event(EventSpec::verify_batches_trusted_aggregator(polygon_zkevm_rollup_id)),
+        ChainConfig::rpc("arbitrum", 42161, "", "eth_getBlockByNumber")
+            .with_rpc_urls(&arbi_rpc_urls)
+            .with_expected_block_time(1),
+    ];
+    debug_assert!(block_fetch_params.iter().map(|chain| chain.name.as_str()).eq(LOOP_CHAINS));
+    for chain in block_fetch_params.iter_mut() {
+        chain.blob_fields = blob_field_chains.split(',').any(|name| name.trim() == chain.name);
+        chain.verify_receipt = verify_receipt_chains.split(',').any(|name| name.trim() == chain.name);
+        if let Some(headers) = config.headers.get(&chain.name) {
+            chain.headers = headers.clone();
+        }
+        if chain.kind == ChainKind::Rpc {
+            chain.keepalive_cycles = config.keepalive_cycles;
+            chain.rpc_retry = config.rpc_retry.clone();
+            chain.reorg_depth = config.reorg_depth;
+        }
+        if chain.kind == ChainKind::Contract {
+            if let Some(addresses) = config.contract_addresses.get(&chain.name) {
+                chain.extra_contract_addresses = addresses.clone();
+            }
+        }
+        if let Some(events) = config.events.get(&chain.name) {
+            for event in events {
+                let event = EventSpec::from_config(event).map_err(|e| {
+                    Error::other(format!("Invalid event {} of {}: {}", event.name, chain.name, e))
+                })?;
+                chain.events.push(event);
+            }
+        }
+        let (confirmations, confirmations_var) = match chain.kind {
+            ChainKind::Rpc => (&rpc_confirmations, "RPC_CONFIRMATIONS"),
+            _ => (&contract_confirmations, "CONTRACT_CONFIRMATIONS"),
+        };
+        chain.confirmations = confirmations
+            .split(',')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| name.trim() == chain.name)
+            .map(|(_, depth)| {
+                depth.trim().parse::<u64>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} depths must be unsigned integers", confirmations_var),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(0);
+        if chain.kind == ChainKind::Rpc {
+            chain.ws_url = ws_rpc_urls
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim() == chain.name)
+                .map(|(_, url)| url.trim().to_string());
+            // Subscribed heads are plain EVM blocks
+            if chain.ws_url.is_some() && chain.method != "eth_getBlockByNumber" {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("WS_RPC_URLS of {} needs eth_getBlockByNumber, not {}", chain.name, chain.method),
+                ));
+            }
+        }
+        // Only blocks requested by a number parameter can be read below the head
+        if chain.kind == ChainKind::Rpc && chain.confirmations > 0 && chain.method != "eth_getBlockByNumber" {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("RPC_CONFIRMATIONS of {} needs eth_getBlockByNumber, not {}", chain.name, chain.method),
+            ));
+        }
+        chain.offset = chain_offsets.get(&chain.name).copied().unwrap_or_default();
+        if chain.offset >= interval {
+            eprintln!(
+                "Offset {:?} of {} is not below the {:?} interval, it delays the next cycle",
+                chain.offset, chain.name, interval
+            );
+        }
+    }
+    // Chains run in offset order, the stable sort keeps the listed order for equal offsets
+    block_fetch_params.sort_by_key(|chain| chain.offset);
+    Ok(block_fetch_params)
+}
+
+impl BlockReader {
+    /// Reads every chain of `chains` once per `LoopOptions::interval` and queues their
+    /// proofs, until `LoopOptions::max_cycles` cycles completed
+    ///
+    /// A chain that fails is skipped for the cycle. Fails after the last cycle if any
+    /// cycle had errors, and never returns without `max_cycles`.
+    pub async fn run_loop(self: Arc<Self>, chains: Vec<ChainConfig>, options: LoopOptions) -> Result<()> {
+        let mut last_cycle_start: Option<Instant> = None;
+        let (mut completed_cycles, mut failed_cycles) = (0u64, 0u64);
+        let mut last_block_hash: Option<H256> = None;
+
+        for chain in chains.iter().filter(|chain| chain.ws_url.is_some()) {
+            tokio::spawn(self.clone().subscribe_new_heads(chain.clone()));
+        }
+
+        if !options.startup_delay.is_zero() {
+            eprintln!("Waiting {:?} before the first cycle", options.startup_delay);
+            sleep(options.startup_delay).await;
+        }
+
+        loop {
+            let wall_clock = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let deadline = next_cycle_deadline(
+                wall_clock,
+                Instant::now(),
+                options.interval,
+                options.max_clock_skew,
+                last_cycle_start,
+                self.catch_up_interval(),
+            );
+
+            // Sleep until the next interval
+            sleep_until(deadline).await;
+            let cycle_start = Instant::now();
+            last_cycle_start = Some(cycle_start);
+
+            // Execute all tasks
+            let mut cycle_succeeded = true;
+            for chain in &chains {
+                sleep_until(cycle_start + chain.offset).await;
+                let operation_permit = self.acquire_operation().await;
+                let provider_permit = self.acquire_provider(chain).await;
+                let chain_work = async {
+                    Ok::<bool, BlockReaderError>(match chain.kind {
+                        ChainKind::Sdk => {
+                            let fetched_number = read_block_number("avail");
+                            let block_number = match fetched_number {
+                                Some(number) => format!("{}", number),
+                                None => String::from(""),
+                            };
+                            match self.fetch_block_hash(
+                                "avail".to_string(),
+                                chain.chain_id,
+                                block_number.as_str(),
+                                last_block_hash,
+                            )
+                            .await
+                            {
+                                Ok(fetched) => {
+                                    let next_number = fetched.height + 1;
+                                    let hash = format!("0x{}", const_hex::encode(fetched.hash.as_bytes()));
+                                    write_state_blocking(move || write_block_number("avail", next_number, Some(&hash)))
+                                        .await?;
+                                    last_block_hash = Some(fetched.hash);
+                                    true
+                                }
+                                Err(e) => {
+                                    // Moves on to the next chain, the block is retried next cycle
+                                    report_chain_error(&chain.name, &e);
+                                    false
+                                }
+                            }
+                        }
+                        ChainKind::Rpc if self.is_subscribed(&chain.name) => {
+                            if debug_enabled() {
+                                eprintln!("{} follows its newHeads subscription, not polling it", chain.name);
+                            }
+                            true
+                        }
+                        ChainKind::Rpc => match self.block_hash_from_rpc(chain).await {
+                            Ok(_) => true,
+                            Err(e) => {
+                                report_chain_error(&chain.name, &e);
+                                false
+                            }
+                        },
+                        ChainKind::Contract => match self.read_contract_events(chain).await {
+                            Ok(_) => true,
+                            Err(e) => {
+                                report_chain_error(&chain.name, &e);
+                                false
+                            }
+                        },
+                    })
+                };
+                let chain_result = match options.chain_timeout {
+                    Some(limit) => match tokio::time::timeout(limit, chain_work).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            eprintln!(
+                                "{} did not finish within the {:?} chain timeout, skipping it this cycle",
+                                chain.name, limit
+                            );
+                            Ok(false)
+                        }
+                    },
+                    None => chain_work.await,
+                };
+                // Like a timeout, a failed chain is skipped this cycle without ending the run
+                let chain_succeeded = match chain_result {
+                    Ok(succeeded) => succeeded,
+                    Err(e) => {
+                        report_chain_error(&chain.name, &e);
+                        false
+                    }
+                };
+
+                if let Some(heartbeat_file) = options.heartbeat_file.as_deref() {
+                    if options.heartbeat_per_chain && chain_succeeded {
+                        if let Err(e) = touch_heartbeat(&format!("{}.{}", heartbeat_file, chain.name)) {
+                            eprintln!("Failed to touch heartbeat file of {}: {}", chain.name, e);
+                        }
+                    }
+                }
+                drop(provider_permit);
+                drop(operation_permit);
+                cycle_succeeded &= chain_succeeded;
+                sleep(Duration::from_millis(1000)).await;
+            }
+
+            if let Some(heartbeat_file) = options.heartbeat_file.as_deref() {
+                if cycle_succeeded {
+                    if let Err(e) = touch_heartbeat(heartbeat_file) {
+                        eprintln!("Failed to touch heartbeat file: {}", e);
+                    }
+                }
+            }
+
+            completed_cycles += 1;
+            if !cycle_succeeded {
+                failed_cycles += 1;
+            }
+            if options.max_cycles.is_some_and(|max_cycles| completed_cycles >= max_cycles) {
+                eprintln!("Completed {} cycles, {} with errors", completed_cycles, failed_cycles);
+                if failed_cycles > 0 {
+                    return Err(Error::other(format!(
+                        "{} of {} cycles had errors",
+                        failed_cycles, completed_cycles
+                    )));
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A chain's RPC endpoints, from the comma separated `list_var` if set, otherwise the
+/// single `url_var` or `default`
+///
+/// The first endpoint is the primary, the rest its fallbacks in order.
+fn rpc_urls_from_env(list_var: &str, url_var: &str, default: &str) -> Vec<String> {
+    let urls: Vec<String> = std::env::var(list_var)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect();
+    if !urls.is_empty() {
+        return urls;
+    }
+    vec![std::env::var(url_var).unwrap_or_else(|_| default.to_string())]
+}
+
+/// Reads an unsigned integer from the env var `var`, `default` when it is unset
+fn env_u64(var: &str, default: u64) -> Result<u64> {
+    match std::env::var(var) {
+        Ok(value) => value.trim().parse::<u64>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} must be an unsigned integer, got '{}'", var, value),
+            )
+        }),
+        Err(_) => Ok(default),
+    }
+}
+
+/// When the next loop cycle starts, given the wall clock time since the unix epoch and
+/// the monotonic `now`
+///
+/// Aligns to the next interval boundary on the wall clock (e.g. if it's 12:03, next is
+/// 12:10) but paces on the monotonic clock, so NTP steps can't cause missed or doubled
+/// cycles.
+fn next_cycle_deadline(
+    wall_clock: Duration,
+    now: Instant,
+    interval: Duration,
+    max_clock_skew: Duration,
+    last_cycle_start: Option<Instant>,
+    catch_up_interval: Option<Duration>,
+) -> Instant {
+    let mut deadline = now + duration_until_next_boundary(wall_clock, interval);
+
+    // Never start cycles closer together than the interval minus the skew tolerance,
+    // even if the wall clock stepped backwards past the last boundary
+    if let Some(last_cycle_start) = last_cycle_start {
+        deadline = deadline.max(last_cycle_start + interval.saturating_sub(max_clock_skew));
+    }
+
+    // A chain that fell behind is caught up on the shorter catch-up interval instead
+    if let (Some(catch_up_interval), Some(last_cycle_start)) = (catch_up_interval, last_cycle_start) {
+        deadline = deadline.min(last_cycle_start + catch_up_interval);
+    }
+    deadline
+}
+
+/// Logs a chain's failed cycle, naming which side failed so a node outage isn't mistaken
+/// for a local state problem
+fn report_chain_error(chain: &str, error: &BlockReaderError) {
+    let source = match error {
+        BlockReaderError::Rpc(_)
+        | BlockReaderError::JsonRpc { .. }
+        | BlockReaderError::Avail(_)
+        | BlockReaderError::Provider(_) => "node request failed",
+        BlockReaderError::Abi(_) | BlockReaderError::InvalidData(_) => "node returned unusable data",
+        BlockReaderError::State(_) => "state write failed",
+    };
+    eprintln!("{}: {}, skipping it this cycle: {}", chain, source, error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backwards_clock_step_does_not_double_a_cycle() {
+        let interval = Duration::from_secs(600);
+        let max_clock_skew = Duration::from_secs(5);
+        let now = Instant::now();
+        // The last cycle started on the 600s boundary 10s ago, then the wall clock
+        // stepped back 20s to just before that boundary
+        let last_cycle_start = now - Duration::from_secs(10);
+        let wall_clock = Duration::from_secs(590);
+
+        assert_eq!(duration_until_next_boundary(wall_clock, interval), Duration::from_secs(10));
+        let deadline = next_cycle_deadline(wall_clock, now, interval, max_clock_skew, Some(last_cycle_start), None);
+        assert_eq!(deadline, last_cycle_start + Duration::from_secs(595));
+
+        // Without a previous cycle there's nothing to double, the boundary is used as is
+        let deadline = next_cycle_deadline(wall_clock, now, interval, max_clock_skew, None, None);
+        assert_eq!(deadline, now + Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs::{self, create_dir_all, File, OpenOptions},
+    io::{BufReader, ErrorKind, Read, Result, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use avail_rust_client::{ext::const_hex, H256};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config::SegmentConfig,
+    sink::{ProofEvent, ProofSink},
+};
+
+/// Segment files are named `segment-<unix millis>.seg` so they sort by creation time
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_EXTENSION: &str = "seg";
+/// One line per closed segment, appended next to the segments
+const INDEX_FILE: &str = "index.jsonl";
+
+/// A forwarded proof as archived in a segment, with everything needed to send it again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentRecord {
+    pub timestamp: u64,
+    pub chain: String,
+    pub chain_id: i32,
+    pub height: u128,
+    pub hash: String,
+    pub label: String,
+    pub proof: String,
+}
+
+impl SegmentRecord {
+    fn from_event(event: &ProofEvent) -> Self {
+        SegmentRecord {
+            timestamp: unix_millis() / 1000,
+            chain: event.chain_name.clone(),
+            chain_id: event.chain_id,
+            height: event.height,
+            hash: format!("0x{}", const_hex::encode(event.hash.as_bytes())),
+            label: event.label.clone(),
+            proof: format!("0x{}", const_hex::encode(&event.abi_encoded_proof)),
+        }
+    }
+
+    /// Rebuilds the proof for sending, outside the pending registry
+    pub fn to_event(&self) -> std::result::Result<ProofEvent, String> {
+        let hash = const_hex::decode(self.hash.trim_start_matches("0x"))
+            .ok()
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or_else(|| format!("invalid hash {}", self.hash))?;
+        let abi_encoded_proof = const_hex::decode(self.proof.trim_start_matches("0x"))
+            .map_err(|e| format!("invalid proof of {}: {}", self.hash, e))?;
+        Ok(ProofEvent {
+            id: 0,
+            chain_name: self.chain.clone(),
+            chain_id: self.chain_id,
+            height: self.height,
+            hash: H256::from_slice(&hash),
+            abi_encoded_proof,
+            label: self.label.clone(),
+        })
+    }
+}
+
+struct OpenSegment {
+    path: PathBuf,
+    file: File,
+    opened: Instant,
+    bytes: u64,
+    records: u64,
+    first_timestamp: Option<u64>,
+    last_timestamp: Option<u64>,
+}
+
+/// Archives every proof to rotating segment files instead of sending it anywhere
+///
+/// Each record is a 4-byte big-endian length followed by the JSON encoded
+/// `SegmentRecord`, flushed to disk before the proof counts as acked. Segments rotate
+/// per `SegmentConfig`, and every closed segment gets a line in `index.jsonl`. A new
+/// segment is started on every run, so a crash leaves at most a truncated last record.
+pub struct SegmentSink {
+    dir: PathBuf,
+    config: SegmentConfig,
+    current: Mutex<Option<OpenSegment>>,
+}
+
+impl SegmentSink {
+    pub fn new(dir: PathBuf, config: SegmentConfig) -> Result<Self> {
+        create_dir_all(&dir)?;
+        Ok(SegmentSink {
+            dir,
+            config,
+            current: Mutex::new(None),
+        })
+    }
+
+    fn open_segment(&self) -> Result<OpenSegment> {
+        let path = self
+            .dir
+            .join(format!("{}{:013}.{}", SEGMENT_PREFIX, unix_millis(), SEGMENT_EXTENSION));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        eprintln!("Writing proofs to segment {}", path.display());
+        Ok(OpenSegment {
+            path,
+            file,
+            opened: Instant::now(),
+            bytes: 0,
+            records: 0,
+            first_timestamp: None,
+            last_timestamp: None,
+        })
+    }
+
+    fn is_full(&self, segment: &OpenSegment) -> bool {
+        segment.bytes >= self.config.max_bytes
+            || segment.opened.elapsed().as_secs() >= self.config.max_age_secs
+    }
+
+    /// Records a closed segment in the index
+    fn close_segment(&self, segment: OpenSegment) {
+        let line = json!({
+            "segment": segment.path.file_name().map(|name| name.to_string_lossy()),
+            "records": segment.records,
+            "bytes": segment.bytes,
+            "first_timestamp": segment.first_timestamp,
+            "last_timestamp": segment.last_timestamp,
+        });
+        let index_path = self.dir.join(INDEX_FILE);
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_path)
+            .and_then(|mut index| writeln!(index, "{}", line));
+        if let Err(e) = appended {
+            eprintln!("Failed to update segment index {}: {}", index_path.display(), e);
+        }
+    }
+
+    fn append(&self, record: &SegmentRecord) -> Result<()> {
+        let body = serde_json::to_vec(record)?;
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&body);
+
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|segment| self.is_full(segment)) {
+            if let Some(full) = current.take() {
+                self.close_segment(full);
+            }
+        }
+        let segment = match current.as_mut() {
+            Some(segment) => segment,
+            None => current.insert(self.open_segment()?),
+        };
+
+        segment.file.write_all(&frame)?;
+        segment.file.sync_data()?;
+        segment.bytes += frame.len() as u64;
+        segment.records += 1;
+        segment.first_timestamp.get_or_insert(record.timestamp);
+        segment.last_timestamp = Some(record.timestamp);
+        Ok(())
+    }
+}
+
+impl ProofSink for SegmentSink {
+    fn deliver(&self, event: &ProofEvent) -> bool {
+        match self.append(&SegmentRecord::from_event(event)) {
+            Ok(()) => {
+                eprintln!("Archived {} of {} in {}", event.label, event.chain_name, self.dir.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to archive {} of {}: {}", event.label, event.chain_name, e);
+                false
+            }
+        }
+    }
+}
+
+impl Drop for SegmentSink {
+    fn drop(&mut self) {
+        if let Some(segment) = self.current.lock().unwrap().take() {
+            self.close_segment(segment);
+        }
+    }
+}
+
+/// The segment files to replay for `path`: the file itself, or all segments of a
+/// directory oldest first
+pub fn segment_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.extension().is_some_and(|extension| extension == SEGMENT_EXTENSION)
+                && file
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(SEGMENT_PREFIX))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads all records of a segment file in order
+///
+/// A record cut short at the end of the file, as left by a crash mid-write, ends the
+/// segment with a warning instead of failing it.
+pub fn read_segment(path: &Path) -> Result<Vec<SegmentRecord>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    loop {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let mut body = vec![0u8; u32::from_be_bytes(length) as usize];
+        if let Err(e) = reader.read_exact(&mut body) {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                eprintln!(
+                    "Segment {} ends in a truncated record after {} records",
+                    path.display(),
+                    records.len()
+                );
+                break;
+            }
+            return Err(e);
+        }
+        records.push(serde_json::from_slice(&body)?);
+    }
+    Ok(records)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,126 @@
+use std::fs::{create_dir_all, read_dir, OpenOptions};
+use std::io::{Result, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use avail_rust_client::ext::const_hex;
+use serde::Serialize;
+
+use crate::block_number_op::{state_dir, state_path};
+
+/// A single forwarded proof as recorded in the sent-log
+#[derive(Debug, Clone, Serialize)]
+pub struct SentRecord {
+    pub timestamp: u64,
+    pub height: u128,
+    pub hash: String,
+    pub acked: bool,
+    /// ABI encoded proof as sent, kept for resending; missing in older records
+    #[serde(skip)]
+    pub payload: Option<Vec<u8>>,
+    /// Tag frame `payload` goes out under on its own, e.g. `datablock.keepalive`;
+    /// missing in older records, which were all `datablock`
+    #[serde(skip)]
+    pub tag: Option<String>,
+}
+
+pub fn append_sent_record(
+    chain_name: &str,
+    height: u128,
+    hash: &str,
+    acked: bool,
+    payload: &[u8],
+    tag: &str,
+) -> Result<()> {
+    // File path
+    let file_path = state_path(&format!("{}-sent.log", chain_name));
+
+    // Ensure the directory exists
+    if let Some(parent_dir) = Path::new(&file_path).parent() {
+        create_dir_all(parent_dir)?;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+
+    // Open the file for appending
+    let mut file = match OpenOptions::new().create(true).append(true).open(&file_path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!(
+                "Failed to open sent-log file '{}'. Check if the path is correct and writable. Error: {}",
+                file_path, e
+            );
+            return Err(e);
+        }
+    };
+
+    // One tab separated record per line: timestamp, height, hash, ack status, payload, tag
+    if let Err(e) = writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t0x{}\t{}",
+        timestamp,
+        height,
+        hash,
+        acked,
+        const_hex::encode(payload),
+        tag
+    ) {
+        eprintln!("Failed to write to sent-log file '{}'. Error: {}", file_path, e);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Returns the last `limit` records of a chain's sent-log, oldest first
+pub fn read_recent_sent_records(chain_name: &str, limit: usize) -> Vec<SentRecord> {
+    // File path
+    let file_path = state_path(&format!("{}-sent.log", chain_name));
+
+    let content = match std::fs::read_to_string(&file_path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error: Could not open sent-log file '{}'. {}", file_path, e);
+            return Vec::new();
+        }
+    };
+
+    let records: Vec<SentRecord> = content.lines().filter_map(parse_sent_record).collect();
+    let skip = records.len().saturating_sub(limit);
+    records.into_iter().skip(skip).collect()
+}
+
+/// Reads all records of a sent-log file, oldest first
+pub fn read_sent_log(path: &Path) -> Result<Vec<SentRecord>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content.lines().filter_map(parse_sent_record).collect())
+}
+
+fn parse_sent_record(line: &str) -> Option<SentRecord> {
+    let mut fields = line.split('\t');
+    Some(SentRecord {
+        timestamp: fields.next()?.parse().ok()?,
+        height: fields.next()?.parse().ok()?,
+        hash: fields.next()?.to_string(),
+        acked: fields.next()?.parse().ok()?,
+        payload: fields.next().and_then(|payload| const_hex::decode(payload).ok()),
+        tag: fields.next().map(String::from),
+    })
+}
+
+/// Chains that have a sent-log
+pub fn sent_log_chains() -> Vec<String> {
+    let Ok(entries) = read_dir(state_dir()) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            file_name.to_str()?.strip_suffix("-sent.log").map(String::from)
+        })
+        .collect()
+}
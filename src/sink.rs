@@ -0,0 +1,1134 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::{Error, ErrorKind, Result, Write},
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use avail_rust_client::{ext::const_hex, H256};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{unbounded_channel, Receiver, UnboundedSender},
+        OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    block_reader::BlockReader,
+    config::{AckMode, ChainOrdering, EndpointMode, SinkConfig},
+    metrics::{Metrics, METRICS},
+    pending::PENDING,
+    sent_log::{append_sent_record, read_recent_sent_records, sent_log_chains},
+    util::{debug_enabled, is_successful_reply, ReplyCriterion},
+};
+
+/// A proof ready to be forwarded to the aggregator
+#[derive(Debug, Clone)]
+pub struct ProofEvent {
+    /// Id in the pending registry, see `PENDING`
+    pub id: u64,
+    pub chain_name: String,
+    pub chain_id: i32,
+    pub height: u128,
+    pub hash: H256,
+    /// ABI encoded proof sent as the payload frame
+    pub abi_encoded_proof: Vec<u8>,
+    /// Describes the payload in logs, e.g. "L2MerkleRoot data"
+    pub label: String,
+}
+
+impl ProofEvent {
+    /// Tag frame of the proof sent on its own, keepalives are tagged
+    /// `datablock.keepalive` instead of `datablock` so the aggregator can tell them from
+    /// a new head
+    pub fn tag(&self) -> &'static str {
+        if self.label == KEEPALIVE_LABEL {
+            "datablock.keepalive"
+        } else {
+            "datablock"
+        }
+    }
+}
+
+/// Label of an unchanged head forwarded as a keepalive, see `Config::keepalive_cycles`
+pub const KEEPALIVE_LABEL: &str = "keepalive";
+
+/// Published on the `on_forward` channel after each delivery, e.g. for `/ws` subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardedProof {
+    pub chain: String,
+    pub height: u128,
+    pub hash: String,
+    pub label: String,
+    pub acked: bool,
+    pub timestamp: u64,
+}
+
+/// Outcome of a single delivery attempt to the aggregator
+#[derive(Clone, Copy, PartialEq)]
+enum Delivery {
+    Acked,
+    /// The aggregator replied but the reply did not match the success criterion
+    Rejected,
+    /// Sending or receiving failed, the attempt may be retried
+    Failed,
+}
+
+/// A destination for forwarded proofs, selected with `--sink`
+///
+/// The methods block on I/O, the ZMQ sink for up to its receive timeout, so async code
+/// calls them through `tokio::task::spawn_blocking` like `spawn_sink` and the `/health`
+/// probe do, keeping the runtime's worker threads free for fetches and REST requests.
+pub trait ProofSink: Send + Sync {
+    /// Delivers a proof, blocking until it is acknowledged or given up on
+    ///
+    /// Returns whether the proof counts as acked.
+    fn deliver(&self, event: &ProofEvent) -> bool;
+
+    /// Delivers several proofs of one chain, one by one unless the sink supports batches
+    ///
+    /// Returns whether each proof counts as acked.
+    fn deliver_batch(&self, events: &[ProofEvent]) -> Vec<bool> {
+        events.iter().map(|event| self.deliver(event)).collect()
+    }
+
+    /// Checks that the sink can take proofs without sending a real one
+    fn probe(&self) -> SinkHealth {
+        SinkHealth {
+            reachable: true,
+            ping_reply: None,
+            error: None,
+        }
+    }
+}
+
+/// Outcome of a sink health probe, served on `/health` and `/ready`
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkHealth {
+    pub reachable: bool,
+    /// Whether the aggregator answered the ping, `None` when not asked or it doesn't
+    /// answer pings
+    pub ping_reply: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Drains `receiver` into `sink` until every sender is dropped, then waits for the
+/// proofs in flight
+///
+/// Each delivery runs on a blocking thread since e.g. the ZMQ calls block for up to
+/// the receive timeout, letting fetches keep going while a slow aggregator is
+/// drained. At most `max_in_flight` deliveries run at once, when the window is
+/// full the queue stops draining and fetches wait on the channel.
+/// Chains with strict ordering (the default) go through their own ordering lane, which
+/// delivers one batch at a time in queue order. Relaxed chains are delivered as soon as
+/// the window allows. A queued batch holds its window slot while it waits in its lane,
+/// so a slow strict chain can take up slots other chains could use.
+/// With a `batch_size` above 1, proofs already queued for the same chain are handed
+/// to the sink together.
+/// The outcome of every delivery is recorded in the chain's sent-log along with its
+/// ack status and published on `on_forward`.
+pub fn spawn_sink(
+    sink: Arc<dyn ProofSink>,
+    sink_config: &SinkConfig,
+    on_forward: broadcast::Sender<ForwardedProof>,
+    mut receiver: Receiver<ProofEvent>,
+) -> JoinHandle<()> {
+    let window = sink_config.max_in_flight.max(1);
+    let batch_size = sink_config.batch_size();
+    let ordering = sink_config.ordering.clone();
+    let in_flight = Arc::new(Semaphore::new(window));
+
+    tokio::spawn(async move {
+        let mut lanes: HashMap<String, OrderingLane> = HashMap::new();
+
+        while let Some(event) = receiver.recv().await {
+            // Take whatever else is already queued, up to the batch size
+            let mut events = vec![event];
+            while events.len() < batch_size {
+                match receiver.try_recv() {
+                    Ok(event) => events.push(event),
+                    Err(_) => break,
+                }
+            }
+
+            for mut batch in split_by_chain(events) {
+                let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                    return;
+                };
+                // Proofs dropped through `/pending` while queued are skipped
+                batch.retain(|event| PENDING.start_delivery(event.id));
+                if batch.is_empty() {
+                    continue;
+                }
+
+                let chain_name = batch[0].chain_name.clone();
+                if ordering.get(&chain_name).copied().unwrap_or_default() == ChainOrdering::Relaxed {
+                    let sink = sink.clone();
+                    let on_forward = on_forward.clone();
+                    tokio::task::spawn_blocking(move || {
+                        deliver_and_record(sink.as_ref(), &batch, &on_forward);
+                        drop(permit);
+                    });
+                } else {
+                    let lane = lanes
+                        .entry(chain_name)
+                        .or_insert_with(|| OrderingLane::spawn(sink.clone(), on_forward.clone()));
+                    let _ = lane.sender.send((batch, permit));
+                }
+            }
+        }
+
+        // Lanes finish the batches they hold once their sender is dropped
+        for (_, lane) in lanes.drain() {
+            drop(lane.sender);
+            let _ = lane.worker.await;
+        }
+        // Every permit is back once the last delivery has finished
+        let _ = in_flight.acquire_many(window as u32).await;
+    })
+}
+
+/// Delivers the batches of one strictly ordered chain one after another
+struct OrderingLane {
+    sender: UnboundedSender<(Vec<ProofEvent>, OwnedSemaphorePermit)>,
+    worker: JoinHandle<()>,
+}
+
+impl OrderingLane {
+    fn spawn(sink: Arc<dyn ProofSink>, on_forward: broadcast::Sender<ForwardedProof>) -> Self {
+        // Unbounded since every queued batch already holds a window permit
+        let (sender, mut receiver) = unbounded_channel::<(Vec<ProofEvent>, OwnedSemaphorePermit)>();
+        let worker = tokio::spawn(async move {
+            while let Some((batch, permit)) = receiver.recv().await {
+                let sink = sink.clone();
+                let on_forward = on_forward.clone();
+                let _ = tokio::task::spawn_blocking(move || {
+                    deliver_and_record(sink.as_ref(), &batch, &on_forward);
+                    drop(permit);
+                })
+                .await;
+            }
+        });
+        OrderingLane { sender, worker }
+    }
+}
+
+/// Hands a batch of one chain to the sink and records the outcome of every proof
+fn deliver_and_record(
+    sink: &dyn ProofSink,
+    batch: &[ProofEvent],
+    on_forward: &broadcast::Sender<ForwardedProof>,
+) {
+    Metrics::incr(&METRICS.proofs_in_flight);
+    let acked = match batch {
+        [event] => vec![sink.deliver(event)],
+        _ => sink.deliver_batch(batch),
+    };
+    Metrics::decr(&METRICS.proofs_in_flight);
+    for (event, acked) in batch.iter().zip(acked) {
+        record_forward(event, acked, on_forward);
+    }
+}
+
+/// Splits queued proofs into runs of the same chain, keeping their order
+fn split_by_chain(events: Vec<ProofEvent>) -> Vec<Vec<ProofEvent>> {
+    let mut batches: Vec<Vec<ProofEvent>> = Vec::new();
+    for event in events {
+        match batches.last_mut() {
+            Some(batch) if batch[0].chain_name == event.chain_name => batch.push(event),
+            _ => batches.push(vec![event]),
+        }
+    }
+    batches
+}
+
+/// Counts a delivery, appends it to the sent-log and publishes it to `/ws` subscribers
+fn record_forward(event: &ProofEvent, acked: bool, on_forward: &broadcast::Sender<ForwardedProof>) {
+    PENDING.finish(event.id);
+    if acked {
+        Metrics::incr(&METRICS.proofs_acked);
+    } else {
+        Metrics::incr(&METRICS.proofs_unacked);
+    }
+
+    let hash_hex = format!("0x{}", const_hex::encode(event.hash.as_bytes()));
+    if let Err(e) =
+        append_sent_record(&event.chain_name, event.height, &hash_hex, acked, &event.abi_encoded_proof, event.tag())
+    {
+        eprintln!("Failed to record forward of {} in sent-log: {}", event.chain_name, e);
+    }
+
+    // Sending only fails when nobody is subscribed
+    let _ = on_forward.send(ForwardedProof {
+        chain: event.chain_name.clone(),
+        height: event.height,
+        hash: hash_hex,
+        label: event.label.clone(),
+        acked,
+        timestamp: unix_timestamp(),
+    });
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// Writes every proof to stdout as one JSON object per line, for piping into e.g. `jq`
+///
+/// Logs go to stderr so they never interleave with the proofs. There is no reply,
+/// a proof written and flushed counts as acked.
+pub struct JsonlSink;
+
+impl ProofSink for JsonlSink {
+    fn deliver(&self, event: &ProofEvent) -> bool {
+        let line = json!({
+            "chain": event.chain_name,
+            "height": event.height,
+            "hash": format!("0x{}", const_hex::encode(event.hash.as_bytes())),
+            "label": event.label,
+            "abi_encoded_proof": format!("0x{}", const_hex::encode(&event.abi_encoded_proof)),
+            "timestamp": unix_timestamp(),
+        });
+
+        let mut stdout = std::io::stdout().lock();
+        match writeln!(stdout, "{}", line).and_then(|_| stdout.flush()) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Failed to write {} of {} to stdout: {}", event.label, event.chain_name, e);
+                false
+            }
+        }
+    }
+}
+
+/// Reply waits at least this long are logged, from `SLOW_REPLY_MS` (default 5000)
+fn slow_reply_threshold() -> Duration {
+    static THRESHOLD: OnceLock<Duration> = OnceLock::new();
+    *THRESHOLD.get_or_init(|| {
+        Duration::from_millis(
+            std::env::var("SLOW_REPLY_MS")
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+                .unwrap_or(5000),
+        )
+    })
+}
+
+/// How long the health probe waits for the connection and for the ping reply
+const SINK_PROBE_TIMEOUT_MS: i32 = 2000;
+/// How long a new PUB socket waits for the aggregator's subscription before its first
+/// proof
+const PUB_SUBSCRIBE_TIMEOUT_MS: i32 = 2000;
+
+/// Socket type the ZMQ sink sends proofs with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZmqSocketType {
+    /// Request/reply, every proof waits for the aggregator's reply
+    Req,
+    /// Publish only, proofs count as delivered once sent and are never confirmed
+    Pub,
+}
+
+impl ZmqSocketType {
+    /// Reads the socket type from `ZMQ_SOCKET_TYPE` (`REQ` or `PUB`), defaults to `REQ`
+    pub fn from_env() -> Self {
+        match std::env::var("ZMQ_SOCKET_TYPE")
+            .unwrap_or_default()
+            .to_uppercase()
+            .as_str()
+        {
+            "PUB" => ZmqSocketType::Pub,
+            "REQ" | "" => ZmqSocketType::Req,
+            other => {
+                eprintln!("Unknown ZMQ_SOCKET_TYPE {}, using REQ", other);
+                ZmqSocketType::Req
+            }
+        }
+    }
+
+    fn zmq_type(self) -> zmq::SocketType {
+        match self {
+            ZmqSocketType::Req => zmq::REQ,
+            // XPUB publishes like PUB but also receives the subscriptions of its peers
+            ZmqSocketType::Pub => zmq::XPUB,
+        }
+    }
+}
+
+/// Delivers proofs to the aggregator over ZMQ REQ/REP, or publishes them over PUB
+///
+/// With `ZMQ_SOCKET_TYPE=PUB` the aggregator subscribes with a SUB socket bound to the
+/// endpoint. A proof then counts as acked once it is sent, there is no reply, receive
+/// timeout or ack criterion, and a proof published while the aggregator is down or
+/// not subscribed is lost without notice. A new socket waits up to 2s for the
+/// subscription before its first proof, since PUB drops whatever it sends before.
+///
+/// All sockets come from one `zmq::Context` and connected REQ sockets are pooled per
+/// endpoint. Deliveries run on blocking threads, and a REQ socket isn't thread-safe
+/// and must strictly alternate send and receive, so each delivery takes a socket out
+/// of the pool for its round trip and puts it back after a reply. With
+/// `max_in_flight` above 1 concurrent deliveries get a socket each. A socket whose
+/// send or receive failed is left in an unknown state and closed instead, the next
+/// attempt connects a fresh one.
+///
+/// `ZMQ_CHANNEL_URL` may list several aggregators, used one at a time or all at once
+/// per `SinkConfig::endpoint_mode`.
+pub struct ZmqSink {
+    /// Endpoints of `ZMQ_CHANNEL_URL` in listed order
+    endpoints: Vec<String>,
+    /// Index into `endpoints` of the one failover currently sends to
+    active_endpoint: AtomicUsize,
+    socket_type: ZmqSocketType,
+    context: zmq::Context,
+    /// Idle connected sockets by endpoint, guarded by a mutex only while taking or
+    /// returning one
+    sockets: Mutex<HashMap<String, Vec<zmq::Socket>>>,
+    reply_criterion: ReplyCriterion,
+    sink_config: SinkConfig,
+    /// Deployment tag appended as a final frame, empty keeps the 3 frame payload
+    namespace: String,
+    /// Session of every endpoint that was sent to
+    sessions: Mutex<HashMap<String, AggregatorSession>>,
+    /// Chains whose recent proofs are to be re-sent after an aggregator reconnected,
+    /// each by its own next delivery so the resend keeps the chain's ordering
+    resend_pending: Mutex<HashSet<String>>,
+    /// Chains whose endpoints `probe` checks, see `with_chains`
+    chains: Vec<String>,
+}
+
+/// What the sink has seen of an aggregator, to notice it came back after a restart
+#[derive(Default)]
+struct AggregatorSession {
+    /// A delivery attempt failed since the last reply
+    disconnected: bool,
+    /// Id of the last `session:<id>` reply frame
+    id: Option<String>,
+}
+
+impl ZmqSink {
+    pub fn new(sink_config: SinkConfig, namespace: String) -> Self {
+        let mut endpoints: Vec<String> = std::env::var("ZMQ_CHANNEL_URL")
+            .unwrap_or_default()
+            .split(',')
+            .map(|endpoint| endpoint.trim().to_string())
+            .filter(|endpoint| !endpoint.is_empty())
+            .collect();
+        if endpoints.is_empty() {
+            endpoints.push("tcp://0.0.0.0:40006".to_string());
+        }
+        if endpoints.len() > 1 {
+            eprintln!(
+                "Sending proofs to {} aggregators ({:?}): {}",
+                endpoints.len(),
+                sink_config.endpoint_mode,
+                endpoints.join(", ")
+            );
+        }
+        let reply_criterion = ReplyCriterion::from_env();
+        let socket_type = ZmqSocketType::from_env();
+        if socket_type == ZmqSocketType::Pub {
+            eprintln!("Publishing proofs over ZMQ PUB, deliveries are not confirmed");
+        }
+
+        ZmqSink {
+            endpoints,
+            active_endpoint: AtomicUsize::new(0),
+            socket_type,
+            context: zmq::Context::new(),
+            sockets: Mutex::new(HashMap::new()),
+            reply_criterion,
+            sink_config,
+            namespace,
+            sessions: Mutex::new(HashMap::new()),
+            resend_pending: Mutex::new(HashSet::new()),
+            chains: Vec::new(),
+        }
+    }
+
+    /// Sets the chains whose proofs go through this sink, so the health probe also
+    /// checks the endpoints they are routed to by `endpoints` or `endpoint_template`
+    pub fn with_chains<S: Into<String>>(mut self, chains: impl IntoIterator<Item = S>) -> Self {
+        self.chains = chains.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The endpoints proofs of `chain_name` are sent to, the one configured for it per
+    /// `SinkConfig::zmq_endpoint` or else those of `ZMQ_CHANNEL_URL`
+    fn endpoints_for(&self, chain_name: &str) -> Vec<String> {
+        match self.sink_config.zmq_endpoint(chain_name) {
+            Some(endpoint) => vec![endpoint],
+            None => self.endpoints.clone(),
+        }
+    }
+
+    /// Tracks the session of the aggregator at `endpoint` from a reply, a reply after
+    /// failed attempts or with a new session id means it may have lost recent proofs
+    fn note_reply(&self, endpoint: &str, frames: &[String]) {
+        let session_id = frames
+            .iter()
+            .find_map(|frame| frame.strip_prefix("session:"))
+            .map(|id| id.trim().to_string());
+        let reconnected = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.entry(endpoint.to_string()).or_default();
+            let new_session = matches!((&session_id, &session.id), (Some(new), Some(old)) if new != old);
+            let reconnected = session.disconnected || new_session;
+            session.disconnected = false;
+            if session_id.is_some() {
+                session.id = session_id;
+            }
+            reconnected
+        };
+
+        if reconnected && self.sink_config.resend_on_reconnect > 0 {
+            let chains = sent_log_chains()
+                .into_iter()
+                .filter(|chain_name| self.endpoints_for(chain_name).iter().any(|e| e == endpoint));
+            self.resend_pending.lock().unwrap().extend(chains);
+        }
+    }
+
+    /// Records how long a reply (or its timeout) took, logging waits above
+    /// `SLOW_REPLY_MS`
+    fn record_reply_wait(&self, chain_name: &str, label: &str, wait: Duration) {
+        METRICS.record_reply_wait(chain_name, wait);
+        if wait >= slow_reply_threshold() {
+            eprintln!("Slow aggregator reply for {} of {}: waited {:?}", label, chain_name, wait);
+        }
+    }
+
+    fn note_failure(&self, endpoint: &str) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default()
+            .disconnected = true;
+    }
+
+    /// Re-sends the last `resend_on_reconnect` proofs of `chain_name` from its sent-log
+    /// if an aggregator it sends to reconnected
+    ///
+    /// Runs within a delivery of the chain, so the resend stays in its ordering lane;
+    /// other chains resend on their own next delivery. Proofs go out under the tag they
+    /// were recorded with. Resent proofs are not recorded in the sent-log again, records
+    /// written before payloads were logged are skipped.
+    fn resend_after_reconnect(&self, chain_name: &str) {
+        if !self.resend_pending.lock().unwrap().remove(chain_name) {
+            return;
+        }
+
+        let window = self.sink_config.resend_on_reconnect;
+        eprintln!("Aggregator reconnected, re-sending the last {} proofs of {}", window, chain_name);
+        for record in read_recent_sent_records(chain_name, window) {
+            let Some(payload) = record.payload else {
+                continue;
+            };
+            let tag = record.tag.as_deref().unwrap_or("datablock");
+            let label = format!("resend of #{} {}", record.height, record.hash);
+            if self.send_with_retries(tag.as_bytes(), payload, chain_name, &label) {
+                Metrics::incr(&METRICS.proofs_resent);
+            }
+        }
+    }
+
+    /// Frames a payload as `[tag, payload, "!!!!!", namespace?]` and delivers it,
+    /// retrying failed attempts with backoff
+    fn send_with_retries(&self, tag: &[u8], payload: Vec<u8>, chain_name: &str, label: &str) -> bool {
+        let mut data: Vec<Vec<u8>> = vec![tag.to_vec(), payload, b"!!!!!".to_vec()];
+        if !self.namespace.is_empty() {
+            data.push(self.namespace.as_bytes().to_vec());
+        }
+
+        let mut attempt = 0;
+        loop {
+            match self.deliver_attempt(&data, chain_name, label) {
+                Delivery::Acked => break true,
+                Delivery::Rejected => break false,
+                Delivery::Failed if attempt < self.sink_config.retries => {
+                    let delay = self.sink_config.backoff(attempt);
+                    attempt += 1;
+                    eprintln!(
+                        "Retrying {} of {} in {:?} (retry {}/{})",
+                        label, chain_name, delay, attempt, self.sink_config.retries
+                    );
+                    sleep(delay);
+                }
+                Delivery::Failed => break false,
+            }
+        }
+    }
+
+    /// An idle socket connected to `endpoint`, or a newly connected one
+    fn checkout_socket(&self, endpoint: &str) -> std::result::Result<zmq::Socket, zmq::Error> {
+        if let Some(socket) = self
+            .sockets
+            .lock()
+            .unwrap()
+            .get_mut(endpoint)
+            .and_then(Vec::pop)
+        {
+            return Ok(socket);
+        }
+        let socket = self.context.socket(self.socket_type.zmq_type())?;
+        if self.socket_type == ZmqSocketType::Req {
+            socket.set_rcvtimeo(self.sink_config.recv_timeout_ms as i32)?;
+        }
+        // A closed REQ socket drops its unsent message, the proof is retried on a fresh
+        // one. PUB sockets are only closed with the sink and get to flush what they hold.
+        socket.set_linger(match self.socket_type {
+            ZmqSocketType::Req => 0,
+            ZmqSocketType::Pub => PUB_SUBSCRIBE_TIMEOUT_MS,
+        })?;
+        socket.connect(endpoint)?;
+        if debug_enabled() {
+            eprintln!("Connected a new {:?} socket to {}", self.socket_type, endpoint);
+        }
+        if self.socket_type == ZmqSocketType::Pub && !Self::await_subscription(&socket, PUB_SUBSCRIBE_TIMEOUT_MS)? {
+            eprintln!("No subscriber at {} yet, publishing anyway", endpoint);
+        }
+        Ok(socket)
+    }
+
+    /// Connects to the aggregator at `endpoint` and sends a `ping` frame
+    ///
+    /// The aggregator counts as reachable once the connection is up, a reply to the
+    /// ping is reported but not required since older aggregators ignore it. With PUB
+    /// nothing is sent, the aggregator is reachable once its SUB socket subscribed.
+    fn probe_endpoint(&self, endpoint: &str) -> SinkHealth {
+        let unreachable = |error: String| SinkHealth {
+            reachable: false,
+            ping_reply: None,
+            error: Some(error),
+        };
+        if self.socket_type == ZmqSocketType::Pub {
+            let socket = match self.context.socket(zmq::XPUB) {
+                Ok(socket) => socket,
+                Err(e) => return unreachable(format!("failed to create XPUB socket: {}", e)),
+            };
+            let _ = socket.set_linger(0);
+            if let Err(e) = socket.connect(endpoint) {
+                return unreachable(format!("failed to connect to {}: {}", endpoint, e));
+            }
+            return match Self::await_subscription(&socket, SINK_PROBE_TIMEOUT_MS) {
+                Ok(true) => SinkHealth {
+                    reachable: true,
+                    ping_reply: None,
+                    error: None,
+                },
+                Ok(false) => unreachable(format!("no subscriber at {}", endpoint)),
+                Err(e) => unreachable(format!("{} not reachable: {}", endpoint, e)),
+            };
+        }
+
+        // A socket of its own, a pooled one may be mid round trip
+        let socket = match self.context.socket(zmq::REQ) {
+            Ok(socket) => socket,
+            Err(e) => return unreachable(format!("failed to create REQ socket: {}", e)),
+        };
+        // Only queue the ping on a completed connection, so a send timeout means the
+        // endpoint isn't reachable
+        let _ = socket.set_immediate(true);
+        let _ = socket.set_sndtimeo(SINK_PROBE_TIMEOUT_MS);
+        let _ = socket.set_rcvtimeo(SINK_PROBE_TIMEOUT_MS);
+        let _ = socket.set_linger(0);
+        if let Err(e) = socket.connect(endpoint) {
+            return unreachable(format!("failed to connect to {}: {}", endpoint, e));
+        }
+        if let Err(e) = socket.send("ping", 0) {
+            return unreachable(format!("{} not reachable: {}", endpoint, e));
+        }
+
+        let ping_reply = socket.recv_multipart(0).ok().map(|_| true);
+        SinkHealth {
+            reachable: true,
+            ping_reply,
+            error: None,
+        }
+    }
+
+    /// Waits up to `timeout_ms` for a peer to subscribe to a publishing socket
+    ///
+    /// A PUB socket drops what it sends before the subscription arrived, so a new
+    /// socket would lose its first proof without this wait.
+    fn await_subscription(socket: &zmq::Socket, timeout_ms: i32) -> std::result::Result<bool, zmq::Error> {
+        socket.set_rcvtimeo(timeout_ms)?;
+        match socket.recv_bytes(0) {
+            Ok(_) => Ok(true),
+            Err(zmq::Error::EAGAIN) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns a socket that finished its round trip to the pool
+    fn checkin_socket(&self, endpoint: String, socket: zmq::Socket) {
+        self.sockets.lock().unwrap().entry(endpoint).or_default().push(socket);
+    }
+
+    /// Makes a single delivery attempt of `data` to the endpoints of the chain
+    ///
+    /// On failover the endpoints are tried in turn from the active one until one doesn't
+    /// fail, which becomes the active endpoint. A rejecting aggregator did answer, so
+    /// it doesn't fail over. On broadcast the proof is sent to every endpoint one after
+    /// another and counts as acked once any aggregator acked it.
+    fn deliver_attempt(&self, data: &[Vec<u8>], chain_name: &str, label: &str) -> Delivery {
+        let endpoints = self.endpoints_for(chain_name);
+        match self.sink_config.endpoint_mode {
+            EndpointMode::Broadcast => {
+                let deliveries: Vec<Delivery> = endpoints
+                    .into_iter()
+                    .map(|endpoint| self.deliver_to(endpoint, data, chain_name, label))
+                    .collect();
+                if deliveries.contains(&Delivery::Acked) {
+                    Delivery::Acked
+                } else if deliveries.contains(&Delivery::Rejected) {
+                    Delivery::Rejected
+                } else {
+                    Delivery::Failed
+                }
+            }
+            EndpointMode::Failover => {
+                let active = self.active_endpoint.load(Ordering::Relaxed) % endpoints.len();
+                for offset in 0..endpoints.len() {
+                    let index = (active + offset) % endpoints.len();
+                    let endpoint = endpoints[index].clone();
+                    let delivery = self.deliver_to(endpoint.clone(), data, chain_name, label);
+                    if delivery == Delivery::Failed {
+                        continue;
+                    }
+                    if index != active {
+                        eprintln!("Failed over to aggregator {}", endpoint);
+                        self.active_endpoint.store(index, Ordering::Relaxed);
+                    }
+                    return delivery;
+                }
+                Delivery::Failed
+            }
+        }
+    }
+
+    /// Delivers `data` to a single endpoint over a pooled socket
+    fn deliver_to(&self, endpoint: String, data: &[Vec<u8>], chain_name: &str, label: &str) -> Delivery {
+        // A socket that failed to connect or send is closed and the send retried on a
+        // fresh one, e.g. while the aggregator restarts
+        let mut send_retry = 0;
+        let socket = loop {
+            let sent = self
+                .checkout_socket(&endpoint)
+                .and_then(|socket| socket.send_multipart(data, 0).map(|_| socket));
+            match sent {
+                Ok(socket) => break socket,
+                Err(e) if send_retry < self.sink_config.send_retries => {
+                    Metrics::incr(&METRICS.zmq_send_errors);
+                    let delay = self.sink_config.backoff(send_retry);
+                    send_retry += 1;
+                    if debug_enabled() {
+                        eprintln!(
+                            "Failed to send {} of {} to {}, reconnecting in {:?} (send retry {}/{}): {}",
+                            label, chain_name, endpoint, delay, send_retry, self.sink_config.send_retries, e
+                        );
+                    }
+                    sleep(delay);
+                }
+                Err(e) => {
+                    Metrics::incr(&METRICS.zmq_send_errors);
+                    Metrics::incr(&METRICS.zmq_send_failures);
+                    eprintln!(
+                        "Failed to send {} of {} to {} after {} attempts: {}",
+                        label,
+                        chain_name,
+                        endpoint,
+                        send_retry + 1,
+                        e
+                    );
+                    self.note_failure(&endpoint);
+                    return Delivery::Failed;
+                }
+            }
+        };
+        if self.socket_type == ZmqSocketType::Pub {
+            // A PUB socket can always send again, and nothing comes back to wait for
+            eprintln!("Published {} of {} to {}", label, chain_name, endpoint);
+            self.checkin_socket(endpoint, socket);
+            return Delivery::Acked;
+        }
+        if self.sink_config.ack_mode == AckMode::None {
+            // The reply is never read, a REQ socket can't send again before it is, so
+            // the socket is closed instead of pooled
+            return Delivery::Acked;
+        }
+
+        // `recv` returns as soon as the reply arrives, or fails after `recv_timeout_ms`
+        let sent_at = Instant::now();
+        let reply = socket.recv_multipart(0);
+        self.record_reply_wait(chain_name, label, sent_at.elapsed());
+        match reply {
+            Ok(frames) => {
+                // The round trip is complete, the socket can take the next proof
+                self.checkin_socket(endpoint.clone(), socket);
+                let frames: Vec<String> = frames
+                    .iter()
+                    .map(|frame| String::from_utf8_lossy(frame).into_owned())
+                    .collect();
+                if debug_enabled() {
+                    eprintln!("Received reply frames for {}: {:?}", label, frames);
+                }
+                self.note_reply(&endpoint, &frames);
+                if is_successful_reply(&frames, self.reply_criterion) {
+                    eprintln!("Received reply for {}: {:?}", label, frames);
+                    Delivery::Acked
+                } else {
+                    eprintln!("Unrecognized reply for {}: {:?}", label, frames);
+                    Delivery::Rejected
+                }
+            }
+            // The receive timeout elapsed, the aggregator may still process the
+            // proof so it is only marked as unacked. The socket still awaits that
+            // reply and is closed.
+            Err(zmq::Error::EAGAIN) => {
+                eprintln!("Timed out waiting for reply for {} of {}", label, chain_name);
+                Metrics::incr(&METRICS.zmq_recv_timeouts);
+                self.note_failure(&endpoint);
+                Delivery::Failed
+            }
+            // Any other error leaves the REQ socket unusable, it is closed and a
+            // fresh one is connected for the next proof
+            Err(e) => {
+                eprintln!("Failed to receive reply for {} of {}: {}", label, chain_name, e);
+                Metrics::incr(&METRICS.zmq_recv_errors);
+                self.note_failure(&endpoint);
+                Delivery::Failed
+            }
+        }
+    }
+}
+
+impl ProofSink for ZmqSink {
+    /// Sends a proof to the aggregator and waits for its multipart reply
+    ///
+    /// Returns `true` only when the reply satisfies the configured `ReplyCriterion`,
+    /// send/receive errors and unrecognized replies are treated as failures.
+    /// Failed deliveries are retried with backoff as configured in `SinkConfig`.
+    ///
+    /// The proof is tagged per `ProofEvent::tag`.
+    fn deliver(&self, event: &ProofEvent) -> bool {
+        if debug_enabled() {
+            eprintln!("abi_encoded_proof: {:?}", event.abi_encoded_proof);
+        }
+        let acked = self.send_with_retries(
+            event.tag().as_bytes(),
+            event.abi_encoded_proof.clone(),
+            &event.chain_name,
+            &event.label,
+        );
+        self.resend_after_reconnect(&event.chain_name);
+        acked
+    }
+
+    /// Probes every aggregator proofs are sent to: those of `ZMQ_CHANNEL_URL` and the
+    /// endpoint each chain is routed to by `endpoints` or `endpoint_template`
+    ///
+    /// The redundant aggregators of `ZMQ_CHANNEL_URL` are reachable while any of them
+    /// is, since proofs still get through, while a chain's own endpoint has to be
+    /// reachable itself. The errors of those that aren't are reported either way.
+    fn probe(&self) -> SinkHealth {
+        let routed: Vec<Option<String>> = self
+            .chains
+            .iter()
+            .map(|chain| self.sink_config.zmq_endpoint(chain))
+            .collect();
+        let own_endpoints: BTreeSet<String> = self
+            .sink_config
+            .endpoints
+            .values()
+            .cloned()
+            .chain(routed.iter().flatten().cloned())
+            .filter(|endpoint| !self.endpoints.contains(endpoint))
+            .collect();
+        // Without known chains any proof may go to the global aggregators
+        let global_probes: Vec<SinkHealth> = if self.chains.is_empty() || routed.iter().any(Option::is_none) {
+            self.endpoints.iter().map(|endpoint| self.probe_endpoint(endpoint)).collect()
+        } else {
+            Vec::new()
+        };
+        let own_probes: Vec<SinkHealth> = own_endpoints
+            .iter()
+            .map(|endpoint| self.probe_endpoint(endpoint))
+            .collect();
+
+        let probes = || global_probes.iter().chain(&own_probes);
+        let errors: Vec<String> = probes().filter_map(|probe| probe.error.clone()).collect();
+        SinkHealth {
+            reachable: (global_probes.is_empty() || global_probes.iter().any(|probe| probe.reachable))
+                && own_probes.iter().all(|probe| probe.reachable)
+                && !(global_probes.is_empty() && own_probes.is_empty()),
+            ping_reply: probes().filter_map(|probe| probe.ping_reply).reduce(|any, reply| any || reply),
+            error: (!errors.is_empty()).then(|| errors.join("; ")),
+        }
+    }
+
+    /// Sends each run of plain proofs as one `datablock.v2` message, acked or not as a
+    /// whole
+    ///
+    /// The batch encoding only carries block hashes, so keepalives and proofs with a
+    /// richer payload, e.g. blob gas fields or a VerifyBatches event, are delivered one
+    /// by one in between, keeping the chain's order.
+    fn deliver_batch(&self, events: &[ProofEvent]) -> Vec<bool> {
+        let mut acked = Vec::with_capacity(events.len());
+        let mut rest = events;
+        while !rest.is_empty() {
+            let run = rest.iter().take_while(|event| is_plain_proof(event)).count();
+            if run < 2 {
+                acked.push(self.deliver(&rest[0]));
+                rest = &rest[1..];
+                continue;
+            }
+
+            let (batch, remaining) = rest.split_at(run);
+            let hashes: Vec<H256> = batch.iter().map(|event| event.hash).collect();
+            let payload = BlockReader::abi_encode_proof_batch(batch[0].chain_id, &hashes);
+            let label = format!("batch of {} {}", batch.len(), batch[0].label);
+            if debug_enabled() {
+                eprintln!("abi_encoded_proof_batch: {:?}", payload);
+            }
+            let batch_acked = self.send_with_retries(b"datablock.v2", payload, &batch[0].chain_name, &label);
+            self.resend_after_reconnect(&batch[0].chain_name);
+            acked.extend(std::iter::repeat_n(batch_acked, batch.len()));
+            rest = remaining;
+        }
+        acked
+    }
+}
+
+/// Whether a proof's payload is the plain `(chainId, hash)` encoding, the only one a
+/// `datablock.v2` batch can carry
+fn is_plain_proof(event: &ProofEvent) -> bool {
+    event.label != KEEPALIVE_LABEL
+        && event.abi_encoded_proof == BlockReader::abi_encode_proof(event.chain_id, &event.hash)
+}
+
+/// Delivers proofs to a co-located aggregator over a Unix domain socket
+///
+/// Each proof is written as a 4 byte big-endian length followed by the ABI encoded
+/// proof. The connection is kept open between proofs and re-established when a write
+/// fails, failed deliveries are retried with backoff as configured in `SinkConfig`.
+/// There is no reply, a proof written and flushed counts as acked.
+pub struct UnixSink {
+    path: PathBuf,
+    sink_config: SinkConfig,
+    connection: Mutex<Option<UnixStream>>,
+}
+
+impl UnixSink {
+    /// Fails when something other than a socket exists at `path`, a missing socket is
+    /// only reported on delivery so the aggregator may start after the reader
+    pub fn new(path: PathBuf, sink_config: SinkConfig) -> Result<Self> {
+        match std::fs::metadata(&path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a Unix domain socket", path.display()),
+                ));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => eprintln!(
+                "No socket at {} yet, proofs are delivered once the aggregator listens on it",
+                path.display()
+            ),
+            Err(e) => {
+                return Err(Error::new(e.kind(), format!("cannot access {}: {}", path.display(), e)));
+            }
+        }
+
+        Ok(UnixSink {
+            path,
+            sink_config,
+            connection: Mutex::new(None),
+        })
+    }
+
+    fn connect(&self) -> std::result::Result<UnixStream, String> {
+        let stream = UnixStream::connect(&self.path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused => format!(
+                "aggregator is not listening on {} ({})",
+                self.path.display(),
+                e
+            ),
+            _ => format!("failed to connect to {}: {}", self.path.display(), e),
+        })?;
+        let timeout = Duration::from_millis(self.sink_config.recv_timeout_ms.max(1));
+        let _ = stream.set_write_timeout(Some(timeout));
+        Ok(stream)
+    }
+
+    /// Writes one length-prefixed frame, reusing the open connection if there is one
+    fn deliver_attempt(&self, frame: &[u8]) -> std::result::Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+        let stream = match connection.as_mut() {
+            Some(stream) => stream,
+            None => connection.insert(self.connect()?),
+        };
+        if let Err(e) = stream.write_all(frame).and_then(|_| stream.flush()) {
+            // The aggregator may have restarted, reconnect on the next attempt
+            *connection = None;
+            return Err(format!("failed to write to {}: {}", self.path.display(), e));
+        }
+        Ok(())
+    }
+}
+
+impl ProofSink for UnixSink {
+    fn deliver(&self, event: &ProofEvent) -> bool {
+        let mut frame = (event.abi_encoded_proof.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&event.abi_encoded_proof);
+
+        let mut attempt = 0;
+        loop {
+            match self.deliver_attempt(&frame) {
+                Ok(()) => {
+                    eprintln!("Sent {} of {} to {}", event.label, event.chain_name, self.path.display());
+                    break true;
+                }
+                Err(e) if attempt < self.sink_config.retries => {
+                    let delay = self.sink_config.backoff(attempt);
+                    attempt += 1;
+                    eprintln!(
+                        "Failed to send {} of {}: {}, retrying in {:?} (retry {}/{})",
+                        event.label, event.chain_name, e, delay, attempt, self.sink_config.retries
+                    );
+                    sleep(delay);
+                }
+                Err(e) => {
+                    eprintln!("Failed to send {} of {}: {}", event.label, event.chain_name, e);
+                    break false;
+                }
+            }
+        }
+    }
+
+    /// Opens a separate connection to check that the aggregator is listening
+    fn probe(&self) -> SinkHealth {
+        match self.connect() {
+            Ok(_) => SinkHealth {
+                reachable: true,
+                ping_reply: None,
+                error: None,
+            },
+            Err(error) => SinkHealth {
+                reachable: false,
+                ping_reply: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delivers a proof to a REP socket answering with `reply`, judged by `criterion`
+    fn deliver_with_reply(reply: Vec<Vec<u8>>, criterion: ReplyCriterion) -> bool {
+        let context = zmq::Context::new();
+        let aggregator = context.socket(zmq::REP).unwrap();
+        aggregator.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = aggregator.get_last_endpoint().unwrap().unwrap();
+        let replier = std::thread::spawn(move || {
+            let request = aggregator.recv_multipart(0).unwrap();
+            aggregator.send_multipart(reply, 0).unwrap();
+            request
+        });
+
+        let sink_config = SinkConfig {
+            recv_timeout_ms: 5000,
+            send_retries: 0,
+            endpoints: HashMap::from([("test".to_string(), endpoint)]),
+            ..SinkConfig::default()
+        };
+        let mut sink = ZmqSink::new(sink_config, String::new());
+        sink.reply_criterion = criterion;
+        let event = ProofEvent {
+            id: 0,
+            chain_name: "test".to_string(),
+            chain_id: 1,
+            height: 1,
+            hash: H256::repeat_byte(1),
+            abi_encoded_proof: BlockReader::abi_encode_proof(1, &H256::repeat_byte(1)),
+            label: "test".to_string(),
+        };
+        let acked = sink.deliver(&event);
+
+        let request = replier.join().unwrap();
+        assert_eq!(request[0], b"datablock");
+        assert_eq!(request[1], event.abi_encoded_proof);
+        acked
+    }
+
+    fn frames(frames: &[&str]) -> Vec<Vec<u8>> {
+        frames.iter().map(|frame| frame.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn first_frame_ok_replies() {
+        let criterion = ReplyCriterion::FirstFrameOk;
+        assert!(deliver_with_reply(frames(&["OK"]), criterion));
+        assert!(deliver_with_reply(frames(&["OK", "session:1"]), criterion));
+        assert!(deliver_with_reply(vec![b"OK".to_vec(), vec![0xff, 0xfe]], criterion));
+        assert!(!deliver_with_reply(frames(&["session:1", "OK"]), criterion));
+        assert!(!deliver_with_reply(frames(&["status:ok"]), criterion));
+        assert!(!deliver_with_reply(frames(&["ok"]), criterion));
+    }
+
+    #[test]
+    fn status_frame_replies() {
+        let criterion = ReplyCriterion::StatusFrame;
+        assert!(deliver_with_reply(frames(&["status:ok"]), criterion));
+        assert!(deliver_with_reply(frames(&["session:1", " Status : OK "]), criterion));
+        assert!(deliver_with_reply(vec![vec![0xff], b"status:ok".to_vec()], criterion));
+        assert!(!deliver_with_reply(frames(&["OK"]), criterion));
+        assert!(!deliver_with_reply(frames(&["session:1", "status:error"]), criterion));
+        assert!(!deliver_with_reply(frames(&["statusok"]), criterion));
+    }
+
+    #[test]
+    fn probes_the_endpoint_of_every_chain() {
+        let context = zmq::Context::new();
+        let bind = || {
+            let aggregator = context.socket(zmq::REP).unwrap();
+            aggregator.bind("tcp://127.0.0.1:*").unwrap();
+            let endpoint = aggregator.get_last_endpoint().unwrap().unwrap();
+            (aggregator, endpoint)
+        };
+        let (_mapped, mapped_endpoint) = bind();
+        let (templated, templated_endpoint) = bind();
+        // The template turns the chain name into the port
+        let templated_chain = templated_endpoint.rsplit(':').next().unwrap().to_string();
+
+        let sink_config = SinkConfig {
+            endpoints: HashMap::from([("mapped".to_string(), mapped_endpoint)]),
+            endpoint_template: Some("tcp://127.0.0.1:{chain}".to_string()),
+            ..SinkConfig::default()
+        };
+        let sink = ZmqSink::new(sink_config, String::new()).with_chains(["mapped", templated_chain.as_str()]);
+        let health = sink.probe();
+        assert!(health.reachable, "{:?}", health.error);
+
+        drop(templated);
+        let health = sink.probe();
+        assert!(!health.reachable);
+        assert!(health.error.unwrap().contains(&templated_endpoint));
+    }
+}
@@ -1,4 +1,13 @@
+use avail_rust_client::ext::const_hex;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    sync::OnceLock,
+    thread::sleep,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 pub fn get_rpc_call_params(
     chain_name: &str,
@@ -7,6 +16,7 @@ pub fn get_rpc_call_params(
     match chain_name {
         "celestia" => vec![],
         "kaanch" => vec![json!(1)],
+        // Tron's EVM-compat endpoint expects the standard (tag, full_transactions) pair
         _ => vec![
             Value::String(block_number_hex.unwrap_or_default()),
             Value::Bool(false),
@@ -20,6 +30,7 @@ pub fn read_rpc_response(
 ) -> Option<(Option<String>, Option<String>)> {
     let result = response.get("result")?;
 
+    // Numbers are normalized to 0x hex so they parse like EVM quantities
     let (hash, number) = match chain_name {
         "celestia" => (
             result
@@ -32,7 +43,8 @@ pub fn read_rpc_response(
                 .get("header")
                 .and_then(|header| header.get("height"))
                 .and_then(Value::as_str)
-                .map(String::from),
+                .and_then(|height| height.parse::<u64>().ok())
+                .map(|height| format!("0x{:x}", height)),
         ),
         "kaanch" => (
             result
@@ -43,17 +55,331 @@ pub fn read_rpc_response(
             result
                 .get(0)
                 .and_then(|last_block| last_block.get("blockNumber"))
-                .and_then(Value::as_number)
-                .map(|val| val.to_string()),
+                .and_then(quantity_to_hex),
         ),
-        _ => (
-            result.get("hash").and_then(Value::as_str).map(String::from),
+        // Tron may return the hash without a 0x prefix, normalize it to 0x hex
+        "tron" => (
             result
-                .get("number")
+                .get("hash")
+                .or_else(|| result.get("blockHash"))
                 .and_then(Value::as_str)
-                .map(String::from),
+                .map(|hash| format!("0x{}", hash.trim_start_matches("0x"))),
+            result.get("number").and_then(quantity_to_hex),
+        ),
+        _ => (
+            result.get("hash").and_then(Value::as_str).map(String::from),
+            result.get("number").and_then(quantity_to_hex),
         ),
     };
 
     Some((hash, number))
 }
+
+/// Normalizes a block number to 0x hex, accepting hex strings with or without the
+/// prefix and the plain integers some buggy nodes return instead
+pub fn quantity_to_hex(number: &Value) -> Option<String> {
+    match number {
+        Value::String(hex) => parse_hex_quantity(hex).map(|number| format!("0x{:x}", number)),
+        Value::Number(number) => number.as_u64().map(|number| format!("0x{:x}", number)),
+        _ => None,
+    }
+}
+
+/// Parses a hex quantity such as `0x1a`, `0x0` or an unprefixed `1a`
+///
+/// A bare `0x` has no digits and is rejected.
+pub fn parse_hex_quantity(quantity: &str) -> Option<u128> {
+    let quantity = quantity.trim();
+    let digits = quantity
+        .strip_prefix("0x")
+        .or_else(|| quantity.strip_prefix("0X"))
+        .unwrap_or(quantity);
+    if digits.is_empty() {
+        return None;
+    }
+    u128::from_str_radix(digits, 16).ok()
+}
+
+/// Decodes a 32 byte block hash given as hex, with or without `0x` and in either case,
+/// or as base64 as some Tendermint/Celestia endpoint versions return it
+pub fn decode_block_hash(hash: &str) -> Option<[u8; 32]> {
+    let hash = hash.trim();
+    let digits = hash
+        .strip_prefix("0x")
+        .or_else(|| hash.strip_prefix("0X"))
+        .unwrap_or(hash);
+    // 64 hex digits and 44 base64 characters can't be mistaken for each other
+    let bytes = if digits.len() == 64 {
+        const_hex::decode(digits).ok()?
+    } else {
+        STANDARD.decode(hash).ok()?
+    };
+    bytes.try_into().ok()
+}
+
+/// Reads the EIP-4844 `blobGasUsed` and `excessBlobGas` fields of an EVM block
+///
+/// Returns `None` for chains or blocks that don't expose both fields.
+pub fn read_blob_gas_fields(response: &Value) -> Option<(u128, u128)> {
+    let result = response.get("result")?;
+    let read_quantity = |field: &str| {
+        result
+            .get(field)
+            .and_then(Value::as_str)
+            .and_then(parse_hex_quantity)
+    };
+
+    Some((read_quantity("blobGasUsed")?, read_quantity("excessBlobGas")?))
+}
+
+/// Success criterion applied to the aggregator's multipart reply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplyCriterion {
+    /// The first reply frame must be exactly "OK"
+    FirstFrameOk,
+    /// Any reply frame must be a status frame reporting ok, e.g. "status:ok"
+    StatusFrame,
+}
+
+impl ReplyCriterion {
+    /// Reads the criterion from `ZMQ_REPLY_CRITERION` (`ok` or `status`), defaults to `ok`
+    pub fn from_env() -> Self {
+        match std::env::var("ZMQ_REPLY_CRITERION")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "status" => ReplyCriterion::StatusFrame,
+            _ => ReplyCriterion::FirstFrameOk,
+        }
+    }
+}
+
+pub fn is_successful_reply(frames: &[String], criterion: ReplyCriterion) -> bool {
+    match criterion {
+        ReplyCriterion::FirstFrameOk => frames.first().is_some_and(|frame| frame == "OK"),
+        ReplyCriterion::StatusFrame => frames.iter().any(|frame| {
+            frame
+                .split_once(':')
+                .is_some_and(|(key, value)| {
+                    key.trim().eq_ignore_ascii_case("status") && value.trim().eq_ignore_ascii_case("ok")
+                })
+        }),
+    }
+}
+
+/// Whether debug output is enabled through the `DEBUG` env var
+pub fn debug_enabled() -> bool {
+    static DEBUG: OnceLock<bool> = OnceLock::new();
+    *DEBUG.get_or_init(|| {
+        std::env::var("DEBUG")
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    })
+}
+
+/// Time from `now` (since the unix epoch) until the next multiple of `interval`
+///
+/// Always within `(0, interval]`, so a wall clock jump can never produce a
+/// negative or larger than one interval wait.
+pub fn duration_until_next_boundary(now: Duration, interval: Duration) -> Duration {
+    let interval_secs = interval.as_secs().max(1);
+    let next_boundary = (now.as_secs() / interval_secs + 1) * interval_secs;
+    Duration::from_secs(next_boundary)
+        .saturating_sub(now)
+        .min(Duration::from_secs(interval_secs))
+}
+
+/// Rewrites the heartbeat file with the current unix time, updating its mtime
+pub fn touch_heartbeat(path: &str) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    fs::write(path, format!("{}", now.as_secs()))
+}
+
+/// Base delay between state file write retries, doubled after each attempt
+const STATE_WRITE_BACKOFF_MS: u64 = 100;
+
+/// Longest delay between state file write retries, however many `STATE_WRITE_RETRIES`
+const STATE_WRITE_MAX_BACKOFF_MS: u64 = 5_000;
+
+/// Number of retries after a transient state file write error, from `STATE_WRITE_RETRIES`
+pub fn state_write_retries() -> u32 {
+    static RETRIES: OnceLock<u32> = OnceLock::new();
+    *RETRIES.get_or_init(|| {
+        std::env::var("STATE_WRITE_RETRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(3)
+    })
+}
+
+/// Whether an I/O error may clear up on its own, e.g. a full disk or an NFS hiccup
+///
+/// Permission and path errors are permanent, retrying them cannot succeed.
+pub fn is_transient_io_error(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::StorageFull
+            | ErrorKind::StaleNetworkFileHandle
+            | ErrorKind::ResourceBusy
+    ) || e.raw_os_error() == Some(5) // EIO
+}
+
+/// Delay before state file write retry `attempt` (from 0), doubling up to
+/// `STATE_WRITE_MAX_BACKOFF_MS`
+pub fn state_write_backoff(attempt: u32) -> Duration {
+    let delay = STATE_WRITE_BACKOFF_MS
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(STATE_WRITE_MAX_BACKOFF_MS);
+    Duration::from_millis(delay)
+}
+
+/// Runs `write`, retrying transient errors with a short exponential backoff
+///
+/// Gives up after `state_write_retries()` retries or on the first permanent error. The
+/// backoff blocks the thread, async code goes through `write_state_blocking`.
+pub fn retry_state_write<T>(file_path: &str, mut write: impl FnMut() -> Result<T>) -> Result<T> {
+    let retries = state_write_retries();
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Err(e) if attempt < retries && is_transient_io_error(&e) => {
+                let delay = state_write_backoff(attempt);
+                attempt += 1;
+                eprintln!(
+                    "Transient error writing '{}': {}. Retrying in {:?} (retry {}/{})",
+                    file_path, e, delay, attempt, retries
+                );
+                sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs a state file write on the blocking pool, so the backoff of `retry_state_write`
+/// doesn't stall the runtime thread of the async caller
+pub async fn write_state_blocking<T: Send + 'static>(
+    write: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(write).await.map_err(Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `eth_getBlockByNumber` body of Tron's EVM-compat endpoint: the hash has no `0x`
+    /// prefix, its first 8 bytes are the block number, and `stateRoot` is a bare `0x`
+    const TRON_BLOCK_RESPONSE: &str = r#"{
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "baseFeePerGas": "0x0",
+            "difficulty": "0x0",
+            "extraData": "0x",
+            "gasLimit": "0x2c7a2a8c",
+            "gasUsed": "0x1db3e41",
+            "hash": "0000000003f3b6a3c5f1b0a28d7d19e4b0c5a1e6f3d2b7a9e8c4d6f1a2b3c4d5",
+            "logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "miner": "0x4b7c1f8bd4fd8b2f3a0b6c5e2d9a1f0c3e7b8d6a",
+            "mixHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "nonce": "0x0000000000000000",
+            "number": "0x3f3b6a3",
+            "parentHash": "0x0000000003f3b6a2e1d4c7b0a9f8e2d5c6b3a4f1e0d9c8b7a6f5e4d3c2b1a0f9",
+            "receiptsRoot": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "size": "0x1c5a",
+            "stateRoot": "0x",
+            "timestamp": "0x66a0b7c8",
+            "totalDifficulty": "0x0",
+            "transactions": [],
+            "transactionsRoot": "0x6a1e4c3b2f0d9e8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c",
+            "uncles": []
+        }
+    }"#;
+
+    #[test]
+    fn reads_tron_block_response() {
+        let response: Value = serde_json::from_str(TRON_BLOCK_RESPONSE).unwrap();
+        let (hash, number) = read_rpc_response(response, "tron").unwrap();
+        assert_eq!(
+            hash.as_deref(),
+            Some("0x0000000003f3b6a3c5f1b0a28d7d19e4b0c5a1e6f3d2b7a9e8c4d6f1a2b3c4d5")
+        );
+        assert_eq!(number.as_deref(), Some("0x3f3b6a3"));
+        assert!(decode_block_hash(&hash.unwrap()).is_some());
+
+        let params = get_rpc_call_params("tron", Some("latest".to_string()));
+        assert_eq!(params, vec![json!("latest"), json!(false)]);
+    }
+
+    #[test]
+    fn parses_block_number_quantities() {
+        assert_eq!(parse_hex_quantity("0x0"), Some(0));
+        assert_eq!(parse_hex_quantity("0x1a"), Some(26));
+        assert_eq!(parse_hex_quantity("0x"), None);
+        assert_eq!(quantity_to_hex(&json!("0x0")).as_deref(), Some("0x0"));
+        assert_eq!(quantity_to_hex(&json!("0x001a")).as_deref(), Some("0x1a"));
+        assert_eq!(quantity_to_hex(&json!(26)).as_deref(), Some("0x1a"));
+
+        for number in [json!("0x1a"), json!(26)] {
+            let response = json!({ "result": { "hash": "0xab", "number": number } });
+            let (_, number) = read_rpc_response(response, "ethereum").unwrap();
+            assert_eq!(number.as_deref().and_then(parse_hex_quantity), Some(26));
+        }
+    }
+
+    #[test]
+    fn normalizes_celestia_and_kaanch_heights_to_hex() {
+        let celestia = json!({ "result": {
+            "commit": { "block_id": { "hash": "AB12" } },
+            "header": { "height": "4660" }
+        } });
+        let (hash, number) = read_rpc_response(celestia, "celestia").unwrap();
+        assert_eq!(hash.as_deref(), Some("AB12"));
+        assert_eq!(number.as_deref(), Some("0x1234"));
+
+        let kaanch = json!({ "result": [{ "blockHash": "0xab", "blockNumber": 4660 }] });
+        let (_, number) = read_rpc_response(kaanch, "kaanch").unwrap();
+        assert_eq!(number.as_deref().and_then(parse_hex_quantity), Some(4660));
+    }
+
+    #[test]
+    fn decodes_hex_and_base64_block_hashes() {
+        let bytes: [u8; 32] = std::array::from_fn(|i| i as u8 * 7);
+        let hex = const_hex::encode(bytes);
+        assert_eq!(decode_block_hash(&format!("0x{}", hex)), Some(bytes));
+        assert_eq!(decode_block_hash(&hex.to_uppercase()), Some(bytes));
+        assert_eq!(decode_block_hash(&format!("0X{}", hex.to_uppercase())), Some(bytes));
+
+        let base64 = STANDARD.encode(bytes);
+        assert_eq!(base64.len(), 44);
+        assert_eq!(decode_block_hash(&base64), Some(bytes));
+
+        // Wrong lengths and non-hex, non-base64 strings are rejected, never a panic
+        assert_eq!(decode_block_hash(&hex[..62]), None);
+        assert_eq!(decode_block_hash(&STANDARD.encode([1u8; 31])), None);
+        assert_eq!(decode_block_hash(&"zz".repeat(32)), None);
+        assert_eq!(decode_block_hash(""), None);
+    }
+
+    #[test]
+    fn boundary_wait_stays_within_one_interval_after_a_backwards_step() {
+        let interval = Duration::from_secs(600);
+        assert_eq!(duration_until_next_boundary(Duration::from_secs(1200), interval), interval);
+        // Stepping back from 1200.5s to 1199.5s waits half a second, never a negative
+        // or longer than one interval wait
+        assert_eq!(
+            duration_until_next_boundary(Duration::from_millis(1_199_500), interval),
+            Duration::from_millis(500)
+        );
+        assert_eq!(duration_until_next_boundary(Duration::ZERO, interval), interval);
+        assert!(duration_until_next_boundary(Duration::from_secs(5), Duration::ZERO) <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,116 @@
+use std::{io::Result, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    sync::broadcast::{self, error::RecvError},
+    time::sleep,
+};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    connect_async, WebSocketStream,
+};
+
+use crate::sink::ForwardedProof;
+
+/// Returns the `Sec-WebSocket-Key` of a request asking for a websocket upgrade
+pub fn websocket_key(request: &str) -> Option<&str> {
+    let mut upgrade = false;
+    let mut key = None;
+    for line in request.lines().skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("upgrade") && value.eq_ignore_ascii_case("websocket") {
+            upgrade = true;
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            key = Some(value);
+        }
+    }
+    key.filter(|_| upgrade)
+}
+
+/// Completes the websocket handshake and streams every forwarded proof as JSON
+///
+/// Runs until the client disconnects or the forward channel closes.
+pub async fn stream_forwards(
+    mut stream: TcpStream,
+    key: &str,
+    on_forward: broadcast::Sender<ForwardedProof>,
+) -> Result<()> {
+    let handshake = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream.write_all(handshake.as_bytes()).await?;
+
+    let mut forwards = on_forward.subscribe();
+    let mut websocket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+
+    loop {
+        tokio::select! {
+            forward = forwards.recv() => match forward {
+                Ok(forward) => {
+                    let json = serde_json::to_string(&forward).unwrap_or_default();
+                    if websocket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Websocket client lagged, skipped {} forwards", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = websocket.next() => match message {
+                // Clients only listen, anything but a close frame is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = websocket.close(None).await;
+    Ok(())
+}
+
+/// Pause before reconnecting `tail` to a dropped `/ws` endpoint
+const TAIL_RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Prints every forward streamed by a running instance's `/ws` as one compact line
+///
+/// Reconnects whenever the connection drops or can't be made, runs until interrupted.
+pub async fn tail_forwards(url: &str) {
+    loop {
+        match connect_async(url).await {
+            Ok((mut websocket, _)) => {
+                eprintln!("Connected to {}", url);
+                while let Some(message) = websocket.next().await {
+                    match message {
+                        Ok(Message::Text(text)) => match serde_json::from_str::<ForwardedProof>(&text) {
+                            Ok(forward) => println!(
+                                "{} {} #{} {} {} {}",
+                                forward.timestamp,
+                                forward.chain,
+                                forward.height,
+                                forward.hash,
+                                forward.label,
+                                if forward.acked { "acked" } else { "UNACKED" }
+                            ),
+                            Err(_) => println!("{}", text),
+                        },
+                        Ok(Message::Close(_)) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                }
+                eprintln!("Connection to {} closed, reconnecting in {:?}", url, TAIL_RECONNECT_DELAY);
+            }
+            Err(e) => eprintln!(
+                "Failed to connect to {}: {}, retrying in {:?}",
+                url, e, TAIL_RECONNECT_DELAY
+            ),
+        }
+        sleep(TAIL_RECONNECT_DELAY).await;
+    }
+}
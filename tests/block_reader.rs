@@ -0,0 +1,181 @@
+mod common;
+
+use avail_block_reader_rust::{
+    block_number_op::read_block_number,
+    config::{PacingConfig, RetryConfig},
+    events::{EventSpec, RootSource},
+    merkle_root_op::{read_last_merkle_root_block, write_last_merkle_root_block},
+    BlockReader, BlockReaderError, ChainConfig,
+};
+use avail_rust_client::H256;
+use common::{evm_block, MockRpcServer, TestReader};
+use serde_json::{json, Value};
+
+const CONTRACT: &str = "0x00000000000000000000000000000000000000c0";
+
+fn hash(byte: u8) -> String {
+    format!("0x{}", format!("{:02x}", byte).repeat(32))
+}
+
+/// A fallback endpoint lagging behind the primary must not move the chain backwards,
+/// and is followed again once it reports a higher head
+#[tokio::test]
+async fn lagging_fallback_endpoint_does_not_regress_the_head() {
+    let primary = MockRpcServer::start().await;
+    primary.set_result("eth_getBlockByNumber", evm_block(100, &hash(0xa1)));
+    let fallback = MockRpcServer::start().await;
+    fallback.set_result("eth_getBlockByNumber", evm_block(90, &hash(0xb1)));
+    let mut chain = ChainConfig::rpc("failover", 5, &primary.url, "eth_getBlockByNumber")
+        .with_rpc_urls(&[primary.url.clone(), fallback.url.clone()]);
+    chain.rpc_retry = RetryConfig::once();
+
+    let test_reader = TestReader::new();
+    test_reader.reader.block_hash_from_rpc(&chain).await.unwrap();
+
+    // The primary goes down, the fallback answers with an older head
+    primary.set_status(Some(503));
+    test_reader.reader.block_hash_from_rpc(&chain).await.unwrap();
+    assert_eq!(fallback.requests().len(), 1);
+
+    // The fallback catches up past the highest head seen
+    fallback.set_result("eth_getBlockByNumber", evm_block(101, &hash(0xb2)));
+    test_reader.reader.block_hash_from_rpc(&chain).await.unwrap();
+
+    let delivered = test_reader.finish().await;
+    let forwarded: Vec<(u128, String)> = delivered
+        .iter()
+        .map(|event| (event.height, format!("{:?}", event.hash)))
+        .collect();
+    assert_eq!(forwarded, vec![(100, hash(0xa1)), (101, hash(0xb2))]);
+    assert_eq!(read_block_number("failover"), Some(101));
+}
+
+/// Failed fetches come back as typed errors and forward nothing
+#[tokio::test]
+async fn rpc_failures_are_returned_by_kind() {
+    let server = MockRpcServer::start().await;
+    let mut chain = ChainConfig::rpc("failing", 5, &server.url, "eth_getBlockByNumber");
+    chain.rpc_retry = RetryConfig::once();
+    let test_reader = TestReader::new();
+
+    // No result set, the mock answers with a "method not found" error object
+    let error = test_reader.reader.block_hash_from_rpc(&chain).await.unwrap_err();
+    assert!(matches!(error, BlockReaderError::JsonRpc { code: -32601, .. }), "{:?}", error);
+
+    server.set_result("eth_getBlockByNumber", json!({ "number": "0x10" }));
+    let error = test_reader.reader.block_hash_from_rpc(&chain).await.unwrap_err();
+    assert!(matches!(error, BlockReaderError::InvalidData(_)), "{:?}", error);
+
+    server.set_result("eth_getBlockByNumber", evm_block(16, "0x1234"));
+    let error = test_reader.reader.block_hash_from_rpc(&chain).await.unwrap_err();
+    assert!(matches!(error, BlockReaderError::InvalidData(_)), "{:?}", error);
+
+    server.set_status(Some(503));
+    let error = test_reader.reader.block_hash_from_rpc(&chain).await.unwrap_err();
+    assert!(matches!(error, BlockReaderError::Rpc(_)), "{:?}", error);
+
+    assert!(test_reader.finish().await.is_empty());
+    assert_eq!(read_block_number("failing"), None);
+}
+
+/// An `L2MerkleRootAdded` log of `CONTRACT` at `block` with root `root` repeated
+fn merkle_root_log(block: u64, root: u8) -> Value {
+    json!({
+        "address": CONTRACT,
+        "topics": [
+            format!("{:?}", EventSpec::l2_merkle_root_added().topic0()),
+            hash(root),
+            format!("0x{:064x}", block * 10),
+        ],
+        "data": "0x",
+        "blockNumber": format!("0x{:x}", block),
+        "logIndex": "0x0",
+    })
+}
+
+/// Block number of an `eth_getLogs` filter bound, e.g. `fromBlock`
+fn filter_block(params: &Value, bound: &str) -> u64 {
+    u64::from_str_radix(params[0][bound].as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+/// An event without a method of its own is read through its `EventSpec`
+#[tokio::test]
+async fn reads_any_event_with_a_root_source() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_blockNumber", json!("0x20"));
+    let event = EventSpec {
+        name: "RootPosted".to_string(),
+        signature: "RootPosted(bytes32)".to_string(),
+        root_source: RootSource::Topic(1),
+        block_number_source: None,
+        label: "RootPosted data".to_string(),
+    };
+    server.set_result(
+        "eth_getLogs",
+        json!([{
+            "address": CONTRACT,
+            "topics": [format!("{:?}", event.topic0()), hash(0xc1)],
+            "data": "0x",
+            "blockNumber": "0x20",
+            "logIndex": "0x0",
+        }]),
+    );
+
+    let test_reader = TestReader::new();
+    test_reader
+        .reader
+        .read_latest_event(&server.url, CONTRACT.parse().unwrap(), 77, "any-event", event)
+        .await
+        .unwrap();
+    assert_eq!(read_last_merkle_root_block("any-event"), Some(32.into()));
+
+    let delivered = test_reader.finish().await;
+    let root: H256 = hash(0xc1).parse().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].height, 32);
+    assert_eq!(delivered[0].hash, root);
+    assert_eq!(delivered[0].abi_encoded_proof, BlockReader::abi_encode_proof(77, &root));
+}
+
+/// A window failing after a window with a deferred log must leave the cursor before
+/// that log, so the next cycle forwards it
+#[tokio::test]
+async fn partial_scan_keeps_the_cursor_before_deferred_logs() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_blockNumber", json!("0x82"));
+    // Blocks 101 to 110 hold two roots of the contract, the window after them fails
+    server.set_responder("eth_getLogs", |params| match filter_block(params, "fromBlock") {
+        101 => Some(json!([merkle_root_log(105, 0xa5), merkle_root_log(108, 0xa8)])),
+        _ => None,
+    });
+    let chain = ChainConfig::contract("partial", 59144, &server.url, CONTRACT)
+        .with_event(EventSpec::l2_merkle_root_added());
+    write_last_merkle_root_block("partial", 100.into(), None).unwrap();
+
+    let test_reader = TestReader::with_pacing(PacingConfig {
+        log_window_blocks: 10,
+        ..PacingConfig::default()
+    });
+    assert!(test_reader.reader.read_contract_events(&chain).await.is_err());
+    assert_eq!(read_last_merkle_root_block("partial"), Some(107.into()));
+
+    // The node recovers, the deferred root is scanned again and forwarded
+    server.set_responder("eth_getLogs", |params| {
+        let from = filter_block(params, "fromBlock");
+        let to = filter_block(params, "toBlock");
+        Some(if (from..=to).contains(&108) {
+            json!([merkle_root_log(108, 0xa8)])
+        } else {
+            json!([])
+        })
+    });
+    test_reader.reader.read_contract_events(&chain).await.unwrap();
+    assert_eq!(read_last_merkle_root_block("partial"), Some(130.into()));
+
+    let delivered = test_reader.finish().await;
+    let forwarded: Vec<(u128, String)> = delivered
+        .iter()
+        .map(|event| (event.height, format!("{:?}", event.hash)))
+        .collect();
+    assert_eq!(forwarded, vec![(105, hash(0xa5)), (108, hash(0xa8))]);
+}
@@ -0,0 +1,297 @@
+//! Test harness shared by the integration tests: an isolated state directory, a sink
+//! recording what it is handed and a mock JSON-RPC endpoint answering canned results
+//!
+//! Each file under `tests/` is its own binary and uses a different part of it.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+use avail_block_reader_rust::{
+    block_number_op::set_state_dir,
+    config::{PacingConfig, ProvidersConfig, SinkConfig},
+    spawn_sink, BlockReader, ProofEvent, ProofSink,
+};
+
+/// Keeps the state of the test binary in a fresh temporary directory, so what it writes
+/// under `block_numbers/` starts empty and never touches the checkout
+///
+/// State files are named after the chain, tests of one binary keep apart by using their
+/// own chain names.
+pub fn isolate_state() {
+    static STATE_DIR: OnceLock<()> = OnceLock::new();
+    STATE_DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("block-reader-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create state dir");
+        assert!(set_state_dir(&dir), "state was used before isolate_state");
+    });
+}
+
+/// A sink keeping every proof it is handed, acked unless told to reject them
+#[derive(Default)]
+pub struct RecordingSink {
+    delivered: Mutex<Vec<ProofEvent>>,
+    reject: AtomicBool,
+}
+
+impl RecordingSink {
+    /// Proofs delivered so far, in delivery order
+    pub fn delivered(&self) -> Vec<ProofEvent> {
+        self.delivered.lock().unwrap().clone()
+    }
+
+    /// Makes further deliveries count as not acked
+    pub fn reject(&self, reject: bool) {
+        self.reject.store(reject, Ordering::SeqCst);
+    }
+}
+
+impl ProofSink for RecordingSink {
+    fn deliver(&self, event: &ProofEvent) -> bool {
+        self.delivered.lock().unwrap().push(event.clone());
+        !self.reject.load(Ordering::SeqCst)
+    }
+}
+
+/// A `BlockReader` whose proofs go through `spawn_sink` to a `RecordingSink`, as in the
+/// binary with the default `SinkConfig`
+pub struct TestReader {
+    pub reader: Arc<BlockReader>,
+    pub sink: Arc<RecordingSink>,
+    sink_task: JoinHandle<()>,
+}
+
+impl TestReader {
+    pub fn new() -> Self {
+        Self::with_pacing(PacingConfig::default())
+    }
+
+    pub fn with_pacing(pacing: PacingConfig) -> Self {
+        isolate_state();
+        let sink = Arc::new(RecordingSink::default());
+        let (proof_sender, proof_receiver) = mpsc::channel(16);
+        let (on_forward, _) = broadcast::channel(16);
+        let sink_task = spawn_sink(sink.clone(), &SinkConfig::default(), on_forward, proof_receiver);
+        let reader = Arc::new(BlockReader::new(
+            proof_sender,
+            "ws://127.0.0.1:1",
+            &ProvidersConfig::default(),
+            4,
+            pacing,
+        ));
+        TestReader { reader, sink, sink_task }
+    }
+
+    /// Stops the reader and waits until the sink has delivered and recorded everything
+    /// queued, returning what it delivered
+    ///
+    /// Every clone of `reader` has to be dropped for the sink to finish.
+    pub async fn finish(self) -> Vec<ProofEvent> {
+        drop(self.reader);
+        self.sink_task.await.expect("sink task");
+        self.sink.delivered()
+    }
+}
+
+/// An HTTP request the mock endpoint received
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    /// Header names lowercased
+    pub headers: HashMap<String, String>,
+    pub body: Value,
+}
+
+/// Computes the result of a call from its params, `None` answers with an error object
+type Responder = Arc<dyn Fn(&Value) -> Option<Value> + Send + Sync>;
+
+/// Turns the answers to the calls of a batch into the body sent back
+type BatchRewrite = Arc<dyn Fn(Vec<Value>) -> Value + Send + Sync>;
+
+#[derive(Default)]
+struct MockState {
+    /// Result by JSON-RPC method
+    results: HashMap<String, Value>,
+    /// Result by JSON-RPC method for results depending on the params
+    responders: HashMap<String, Responder>,
+    /// Applied to the answers of a batch request when set
+    batch_rewrite: Option<BatchRewrite>,
+    /// Answered instead of a result when set, e.g. 503 for an endpoint that is down
+    status: Option<u16>,
+    delay: Duration,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A JSON-RPC endpoint on a local port answering each method with a canned result
+///
+/// Unknown methods get a `-32601` error object. Every connection serves one request.
+pub struct MockRpcServer {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockRpcServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock RPC server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+        MockRpcServer { url, state }
+    }
+
+    /// Answers `method` with `result` from now on
+    pub fn set_result(&self, method: &str, result: Value) {
+        self.state.lock().unwrap().results.insert(method.to_string(), result);
+    }
+
+    /// Answers `method` with what `responder` returns for the call's params from now on,
+    /// an error object for `None`
+    pub fn set_responder(&self, method: &str, responder: impl Fn(&Value) -> Option<Value> + Send + Sync + 'static) {
+        self.state.lock().unwrap().responders.insert(method.to_string(), Arc::new(responder));
+    }
+
+    /// Sends what `rewrite` makes of the answers to a batch instead of the answers in
+    /// call order, e.g. to reorder or drop some
+    pub fn set_batch_rewrite(&self, rewrite: impl Fn(Vec<Value>) -> Value + Send + Sync + 'static) {
+        self.state.lock().unwrap().batch_rewrite = Some(Arc::new(rewrite));
+    }
+
+    /// Answers every request with this HTTP status and no JSON body, `None` to answer
+    /// normally again
+    pub fn set_status(&self, status: Option<u16>) {
+        self.state.lock().unwrap().status = status;
+    }
+
+    /// Waits this long before answering
+    pub fn set_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().delay = delay;
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let head_end = loop {
+        if let Some(position) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => data.extend_from_slice(&buffer[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let headers: HashMap<String, String> = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let content_length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or_default();
+    while data.len() < head_end + content_length {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => data.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let body: Value = serde_json::from_slice(&data[head_end..head_end + content_length]).unwrap_or_default();
+
+    let (delay, status, answer) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            headers,
+            body: body.clone(),
+        });
+        let answer = match &body {
+            Value::Array(calls) => {
+                let answers = calls.iter().map(|call| answer_call(&state, call)).collect();
+                match &state.batch_rewrite {
+                    Some(rewrite) => rewrite(answers),
+                    None => Value::Array(answers),
+                }
+            }
+            call => answer_call(&state, call),
+        };
+        (state.delay, state.status, answer)
+    };
+    tokio::time::sleep(delay).await;
+
+    let response = match status {
+        Some(status) => format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: text/plain\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndown",
+            status
+        ),
+        None => {
+            let answer = answer.to_string();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                answer.len(),
+                answer
+            )
+        }
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn answer_call(state: &MockState, call: &Value) -> Value {
+    let id = call.get("id").cloned().unwrap_or(json!(1));
+    let method = call.get("method").and_then(Value::as_str).unwrap_or_default();
+    let result = match state.responders.get(method) {
+        Some(responder) => match responder(call.get("params").unwrap_or(&Value::Null)) {
+            Some(result) => Some(result),
+            None => {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32005, "message": "query returned more than 10000 results" }
+                })
+            }
+        },
+        None => state.results.get(method).cloned(),
+    };
+    match result {
+        Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        None => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32601, "message": format!("method {} not found", method) }
+        }),
+    }
+}
+
+/// An `eth_getBlockByNumber` result of block `number` with hash `hash`
+pub fn evm_block(number: u64, hash: &str) -> Value {
+    json!({
+        "number": format!("0x{:x}", number),
+        "hash": hash,
+        "parentHash": format!("0x{}", "00".repeat(32)),
+    })
+}
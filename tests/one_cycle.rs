@@ -0,0 +1,86 @@
+mod common;
+
+use std::time::Duration;
+
+use avail_block_reader_rust::{
+    block_number_op::{read_block_number, state_dir},
+    config::RetryConfig,
+    run_loop::LoopOptions,
+    sent_log::read_recent_sent_records,
+    BlockReader, ChainConfig,
+};
+use avail_rust_client::H256;
+use common::{evm_block, MockRpcServer, TestReader};
+
+const HASH: &str = "0x1f6f3ff9e1c32a2ad3a9d6c0cc5e3f8d4a2b9e7c6d5f4e3a2b1c0d9e8f7a6b5c";
+
+/// A single loop cycle, starting on the next second
+fn one_cycle(heartbeat_file: Option<String>) -> LoopOptions {
+    LoopOptions {
+        interval: Duration::from_secs(1),
+        max_clock_skew: Duration::ZERO,
+        startup_delay: Duration::ZERO,
+        chain_timeout: Some(Duration::from_secs(10)),
+        max_cycles: Some(1),
+        heartbeat_file,
+        heartbeat_per_chain: false,
+    }
+}
+
+/// One loop cycle of an RPC chain: fetch the head, encode its proof, deliver it and
+/// persist the advanced state and the sent-log record
+#[tokio::test]
+async fn one_cycle_forwards_the_head_and_advances_state() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_getBlockByNumber", evm_block(26, HASH));
+    let chain = ChainConfig::rpc("cycle", 7, &server.url, "eth_getBlockByNumber");
+
+    let test_reader = TestReader::new();
+    let heartbeat_file = state_dir().join("cycle.heartbeat");
+    test_reader
+        .reader
+        .clone()
+        .run_loop(vec![chain], one_cycle(Some(heartbeat_file.to_string_lossy().into_owned())))
+        .await
+        .unwrap();
+    assert!(heartbeat_file.exists());
+    let delivered = test_reader.finish().await;
+
+    let hash: H256 = HASH.parse().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].chain_name, "cycle");
+    assert_eq!(delivered[0].height, 26);
+    assert_eq!(delivered[0].hash, hash);
+    assert_eq!(delivered[0].abi_encoded_proof, BlockReader::abi_encode_proof(7, &hash));
+
+    assert_eq!(read_block_number("cycle"), Some(26));
+    let records = read_recent_sent_records("cycle", 10);
+    assert_eq!(records.len(), 1);
+    assert!(records[0].acked);
+    assert_eq!(records[0].hash, HASH);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].body["params"][0], "latest");
+}
+
+/// A cycle whose chain fails still completes, and the loop reports it once done
+#[tokio::test]
+async fn failed_cycle_fails_the_loop() {
+    let server = MockRpcServer::start().await;
+    server.set_status(Some(503));
+    let mut chain = ChainConfig::rpc("failing-cycle", 7, &server.url, "eth_getBlockByNumber");
+    chain.rpc_retry = RetryConfig::once();
+
+    let test_reader = TestReader::new();
+    let error = test_reader
+        .reader
+        .clone()
+        .run_loop(vec![chain], one_cycle(None))
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "1 of 1 cycles had errors");
+    assert!(test_reader.finish().await.is_empty());
+    assert_eq!(read_block_number("failing-cycle"), None);
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use avail_block_reader_rust::{
+    config::RetryConfig,
+    rpc_call::rpc::{rpc_call, rpc_call_batch, RpcTimeout},
+};
+use common::MockRpcServer;
+use serde_json::{json, Value};
+use tokio::time::Instant;
+
+#[tokio::test]
+async fn slow_endpoint_times_out() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_blockNumber", json!("0x1"));
+    server.set_delay(Duration::from_secs(3));
+
+    let retry = RetryConfig {
+        timeout_ms: Some(1000),
+        ..RetryConfig::once()
+    };
+    let started = Instant::now();
+    let error = rpc_call(&server.url, "eth_blockNumber", vec![], None, &BTreeMap::new(), &retry)
+        .await
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let timeout = error.downcast_ref::<RpcTimeout>().expect("an RpcTimeout");
+    assert_eq!(timeout.url, server.url);
+    assert_eq!(error.to_string(), format!("RPC {} timed out after 1s", server.url));
+}
+
+fn block_number_batch() -> Vec<(String, Vec<Value>)> {
+    vec![
+        ("eth_blockNumber".to_string(), vec![]),
+        ("eth_chainId".to_string(), vec![]),
+    ]
+}
+
+#[tokio::test]
+async fn batch_responses_are_matched_by_id() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_blockNumber", json!("0x10"));
+    server.set_result("eth_chainId", json!("0x1"));
+    server.set_batch_rewrite(|mut answers| {
+        answers.reverse();
+        Value::Array(answers)
+    });
+
+    let responses = rpc_call_batch(
+        &server.url,
+        block_number_batch(),
+        None,
+        &BTreeMap::new(),
+        &RetryConfig::once(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(responses[0]["result"], json!("0x10"));
+    assert_eq!(responses[1]["result"], json!("0x1"));
+}
+
+#[tokio::test]
+async fn batch_missing_a_response_fails() {
+    let server = MockRpcServer::start().await;
+    server.set_result("eth_blockNumber", json!("0x10"));
+    server.set_result("eth_chainId", json!("0x1"));
+    server.set_batch_rewrite(|answers| Value::Array(answers.into_iter().take(1).collect()));
+
+    let error = rpc_call_batch(
+        &server.url,
+        block_number_batch(),
+        None,
+        &BTreeMap::new(),
+        &RetryConfig::once(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.to_string(), "no response to eth_chainId (id 1) in the batch");
+}
+
+#[tokio::test]
+async fn batch_answered_with_a_single_error_fails() {
+    let server = MockRpcServer::start().await;
+    server.set_batch_rewrite(|_| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": { "code": -32600, "message": "batch requests are not supported" }
+        })
+    });
+
+    let error = rpc_call_batch(
+        &server.url,
+        block_number_batch(),
+        None,
+        &BTreeMap::new(),
+        &RetryConfig::once(),
+    )
+    .await
+    .unwrap_err();
+    let message = error.to_string();
+    assert!(message.starts_with(&format!("{} rejected the batch of 2", server.url)), "{}", message);
+    assert!(message.contains("batch requests are not supported"), "{}", message);
+}